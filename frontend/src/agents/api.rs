@@ -1,7 +1,11 @@
 use crate::{
     agents::toast::{Msg as ToastAgentMsg, ToastAgent},
     components::toast::{ToastBody, ToastStatus},
-    model::{ApiResponse, Artist, Config, DataType, PostSong, RequestParams, Song},
+    model::{
+        ApiResponse, Artist, Config, DataType, HistoryParams, NowPlaying, Play, PlayCount,
+        PostEnded, PostKey, PostPosition, PostQueueEdit, PostSeek, PostSong, PostTempo, QueueEntry,
+        RequestParams, Session, Song, SuggestParams, Suggestion, SINGER_KEY,
+    },
 };
 use anyhow::{format_err, Error};
 use log::trace;
@@ -11,7 +15,11 @@ use std::time::Duration;
 use yew::{
     agent::{Dispatched, Dispatcher},
     format::{Json, Nothing},
-    services::{fetch, FetchService, IntervalService, Task},
+    services::{
+        fetch,
+        storage::{Area, StorageService},
+        FetchService, IntervalService, Task,
+    },
     worker::*,
 };
 
@@ -31,18 +39,33 @@ pub enum Request {
     GetSongs(RequestParams),
     GetArtists(RequestParams),
     GetQueue,
+    GetNowPlaying,
+    GetHistory(HistoryParams),
+    GetStats(HistoryParams),
+    GetSessions,
+    Suggest(String),
     AddSong(u64),
     PlaySong(u64),
     AddFavorite(u64),
     RemoveFavorite(u64),
     Stop,
+    Pause,
+    Resume,
+    Restart,
+    Seek(u32),
     NextSong,
     ClearQueue,
+    RemoveEntry(u64),
+    MoveEntry { id: u64, position: usize },
+    SwapEntries(u64, u64),
+    SetKey { id: u64, key: i8 },
+    SetTempo { id: u64, tempo: u8 },
     Config,
     PlayerNextSong,
-    FetchMp3(String),
+    FetchAudio(String),
     FetchCdg(String),
-    Ended,
+    Position { id: u64, elapsed: u64 },
+    Ended(u64),
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -50,17 +73,32 @@ pub enum RequestType {
     GetSongs,
     GetArtists,
     GetQueue,
+    GetNowPlaying,
+    GetHistory,
+    GetStats,
+    GetSessions,
+    Suggest,
     AddSong,
     PlaySong,
     AddFavorite,
     RemoveFavorite,
     Stop,
+    Pause,
+    Resume,
+    Restart,
+    Seek,
     NextSong,
     ClearQueue,
+    RemoveEntry,
+    MoveEntry,
+    SwapEntries,
+    SetKey,
+    SetTempo,
     Config,
     PlayerNextSong,
-    FetchMp3,
+    FetchAudio,
     FetchCdg,
+    Position,
     Ended,
 }
 
@@ -80,13 +118,26 @@ pub enum ResponseData {
         artists: Vec<Artist>,
         total_pages: u32,
     },
-    Queue(Vec<Song>),
+    Queue(Vec<QueueEntry>),
+    NowPlaying(NowPlaying),
+    History {
+        plays: Vec<Play>,
+        total_pages: u32,
+    },
+    Stats {
+        songs: Vec<PlayCount>,
+        artists: Vec<PlayCount>,
+    },
+    Sessions(Vec<Session>),
     Config(Config),
+    Suggestions(Vec<Suggestion>),
     PlayerNextSong {
-        mp3: String,
+        entry_id: u64,
+        audio: String,
         cdg: String,
+        tempo: u8,
     },
-    FileMp3(Vec<u8>),
+    FileAudio(Vec<u8>),
     FileCdg(Vec<u8>),
     Empty,
 }
@@ -95,6 +146,9 @@ pub struct ApiAgent {
     link: AgentLink<ApiAgent>,
     fetch_service: FetchService,
     fetch_tasks: Vec<fetch::FetchTask>,
+    //Replacing it drops the previous fetch, so suggestions for an older query
+    //never arrive after newer ones
+    suggest_task: Option<fetch::FetchTask>,
     #[allow(dead_code)]
     job: Box<dyn Task>,
     toast_dispatcher: Dispatcher<ToastAgent>,
@@ -115,6 +169,7 @@ impl Agent for ApiAgent {
             link,
             fetch_service: FetchService::new(),
             fetch_tasks: vec![],
+            suggest_task: None,
             job: Box::new(handle),
             toast_dispatcher: ToastAgent::dispatcher(),
         }
@@ -145,17 +200,37 @@ impl Agent for ApiAgent {
     fn handle_input(&mut self, msg: Self::Input, who: HandlerId) {
         match msg {
             Request::GetSongs(params) => {
-                let fetch_task = self.get_data(who, RequestType::GetSongs, Some(params));
+                let fetch_task = self.get_data(who, RequestType::GetSongs, query_string(&params));
                 self.fetch_tasks.push(fetch_task);
             }
             Request::GetArtists(params) => {
-                let fetch_task = self.get_data(who, RequestType::GetArtists, Some(params));
+                let fetch_task = self.get_data(who, RequestType::GetArtists, query_string(&params));
                 self.fetch_tasks.push(fetch_task);
             }
             Request::GetQueue => {
-                let fetch_task = self.get_data(who, RequestType::GetQueue, None);
+                let fetch_task = self.get_data(who, RequestType::GetQueue, String::new());
                 self.fetch_tasks.push(fetch_task);
             }
+            Request::GetNowPlaying => {
+                let fetch_task = self.get_data(who, RequestType::GetNowPlaying, String::new());
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::GetHistory(params) => {
+                let fetch_task = self.get_data(who, RequestType::GetHistory, query_string(&params));
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::GetStats(params) => {
+                let fetch_task = self.get_data(who, RequestType::GetStats, query_string(&params));
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::GetSessions => {
+                let fetch_task = self.get_data(who, RequestType::GetSessions, String::new());
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::Suggest(q) => {
+                let params = query_string(&SuggestParams { q });
+                self.suggest_task = Some(self.get_data(who, RequestType::Suggest, params));
+            }
             Request::AddSong(id) => {
                 let fetch_task = self.send_command(who, RequestType::AddSong, Some(id));
                 self.fetch_tasks.push(fetch_task);
@@ -172,10 +247,63 @@ impl Agent for ApiAgent {
                 let fetch_task = self.send_command(who, RequestType::ClearQueue, None);
                 self.fetch_tasks.push(fetch_task);
             }
+            Request::RemoveEntry(id) => {
+                let edit = PostQueueEdit {
+                    id,
+                    position: None,
+                    other: None,
+                };
+                let fetch_task = self.post_form(who, RequestType::RemoveEntry, edit);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::MoveEntry { id, position } => {
+                let edit = PostQueueEdit {
+                    id,
+                    position: Some(position),
+                    other: None,
+                };
+                let fetch_task = self.post_form(who, RequestType::MoveEntry, edit);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::SwapEntries(id, other) => {
+                let edit = PostQueueEdit {
+                    id,
+                    position: None,
+                    other: Some(other),
+                };
+                let fetch_task = self.post_form(who, RequestType::SwapEntries, edit);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::SetKey { id, key } => {
+                let fetch_task = self.post_form(who, RequestType::SetKey, PostKey { id, key });
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::SetTempo { id, tempo } => {
+                let tempo = PostTempo { id, tempo };
+                let fetch_task = self.post_form(who, RequestType::SetTempo, tempo);
+                self.fetch_tasks.push(fetch_task);
+            }
             Request::Stop => {
                 let fetch_task = self.send_command(who, RequestType::Stop, None);
                 self.fetch_tasks.push(fetch_task);
             }
+            Request::Pause => {
+                let fetch_task = self.send_command(who, RequestType::Pause, None);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::Resume => {
+                let fetch_task = self.send_command(who, RequestType::Resume, None);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::Restart => {
+                let fetch_task = self.send_command(who, RequestType::Restart, None);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::Seek(seconds) => {
+                let seek = PostSeek { seconds };
+                let fetch_task = self.post_form(who, RequestType::Seek, seek);
+                self.fetch_tasks.push(fetch_task);
+            }
             Request::AddFavorite(id) => {
                 let fetch_task = self.send_command(who, RequestType::AddFavorite, Some(id));
                 self.fetch_tasks.push(fetch_task);
@@ -185,23 +313,28 @@ impl Agent for ApiAgent {
                 self.fetch_tasks.push(fetch_task);
             }
             Request::Config => {
-                let fetch_task = self.get_data(who, RequestType::Config, None);
+                let fetch_task = self.get_data(who, RequestType::Config, String::new());
                 self.fetch_tasks.push(fetch_task);
             }
             Request::PlayerNextSong => {
-                let fetch_task = self.get_data(who, RequestType::PlayerNextSong, None);
+                let fetch_task = self.get_data(who, RequestType::PlayerNextSong, String::new());
                 self.fetch_tasks.push(fetch_task);
             }
-            Request::FetchMp3(file_name) => {
-                let fetch_task = self.fetch_file(who, RequestType::FetchMp3, file_name);
+            Request::FetchAudio(file_name) => {
+                let fetch_task = self.fetch_file(who, RequestType::FetchAudio, file_name);
                 self.fetch_tasks.push(fetch_task);
             }
             Request::FetchCdg(file_name) => {
                 let fetch_task = self.fetch_file(who, RequestType::FetchCdg, file_name);
                 self.fetch_tasks.push(fetch_task);
             }
-            Request::Ended => {
-                let fetch_task = self.send_command(who, RequestType::Ended, None);
+            Request::Position { id, elapsed } => {
+                let position = PostPosition { id, elapsed };
+                let fetch_task = self.post_form(who, RequestType::Position, position);
+                self.fetch_tasks.push(fetch_task);
+            }
+            Request::Ended(id) => {
+                let fetch_task = self.post_form(who, RequestType::Ended, PostEnded { id });
                 self.fetch_tasks.push(fetch_task);
             }
         }
@@ -213,7 +346,7 @@ impl ApiAgent {
         &mut self,
         who: HandlerId,
         request_type: RequestType,
-        params: Option<RequestParams>,
+        params: String,
     ) -> fetch::FetchTask {
         trace!("Fetching data from API");
 
@@ -232,10 +365,30 @@ impl ApiAgent {
                             total_pages: data.total_pages.unwrap_or(0),
                         },
                         DataType::Queue(songs) => ResponseData::Queue(songs),
+                        DataType::NowPlaying(now_playing) => ResponseData::NowPlaying(now_playing),
+                        DataType::History(plays) => ResponseData::History {
+                            plays,
+                            total_pages: data.total_pages.unwrap_or(0),
+                        },
+                        DataType::Stats { songs, artists } => {
+                            ResponseData::Stats { songs, artists }
+                        }
+                        DataType::Sessions(sessions) => ResponseData::Sessions(sessions),
                         DataType::Config(config) => ResponseData::Config(config),
-                        DataType::PlayerNextSong { mp3, cdg } => {
-                            ResponseData::PlayerNextSong { mp3, cdg }
+                        DataType::Suggestions(suggestions) => {
+                            ResponseData::Suggestions(suggestions)
                         }
+                        DataType::PlayerNextSong {
+                            entry_id,
+                            audio,
+                            cdg,
+                            tempo,
+                        } => ResponseData::PlayerNextSong {
+                            entry_id,
+                            audio,
+                            cdg,
+                            tempo,
+                        },
                     };
 
                     return Msg::Return {
@@ -255,7 +408,6 @@ impl ApiAgent {
             },
         );
 
-        let params: String = serde_urlencoded::to_string(&params.unwrap_or_default()).unwrap();
         let request = fetch::Request::get(&format!("/api/{}?{}", request_type.path(), params))
             .body(Nothing)
             .unwrap();
@@ -268,6 +420,50 @@ impl ApiAgent {
         who: HandlerId,
         request_type: RequestType,
         song_id: Option<u64>,
+    ) -> fetch::FetchTask {
+        let request = if let Some(id) = song_id {
+            //Songs are queued under the singer's name, if they've entered one
+            let singer = match request_type {
+                RequestType::AddSong => singer_name(),
+                _ => None,
+            };
+            let body = serde_urlencoded::to_string(PostSong { hash: id, singer })
+                .map_err(|_| format_err!("Failed to serialize data"));
+
+            fetch::Request::post(&format!("/api/{}", request_type.path()))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(body)
+                .unwrap()
+        } else {
+            fetch::Request::post(&format!("/api/{}", request_type.path()))
+                .body(Ok(String::from("")))
+                .unwrap()
+        };
+
+        self.post(who, request_type, request)
+    }
+
+    fn post_form<T: Serialize>(
+        &mut self,
+        who: HandlerId,
+        request_type: RequestType,
+        form: T,
+    ) -> fetch::FetchTask {
+        let body =
+            serde_urlencoded::to_string(form).map_err(|_| format_err!("Failed to serialize data"));
+        let request = fetch::Request::post(&format!("/api/{}", request_type.path()))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .unwrap();
+
+        self.post(who, request_type, request)
+    }
+
+    fn post(
+        &mut self,
+        who: HandlerId,
+        request_type: RequestType,
+        request: fetch::Request<Result<String, Error>>,
     ) -> fetch::FetchTask {
         let callback = self.link.callback(
             move |response: fetch::Response<Json<Result<ApiResponse, Error>>>| {
@@ -296,20 +492,6 @@ impl ApiAgent {
             },
         );
 
-        let request = if let Some(id) = song_id {
-            let body = serde_urlencoded::to_string(PostSong { hash: id })
-                .map_err(|_| format_err!("Failed to serialize data"));
-
-            fetch::Request::post(&format!("/api/{}", request_type.path()))
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .body(body)
-                .unwrap()
-        } else {
-            fetch::Request::post(&format!("/api/{}", request_type.path()))
-                .body(Ok(String::from("")))
-                .unwrap()
-        };
-
         self.fetch_service.fetch(request, callback).unwrap()
     }
 
@@ -334,7 +516,7 @@ impl ApiAgent {
 
                     let response_data = match request_type {
                         RequestType::FetchCdg => ResponseData::FileCdg(body.unwrap()),
-                        _ => ResponseData::FileMp3(body.unwrap()),
+                        _ => ResponseData::FileAudio(body.unwrap()),
                     };
 
                     let response = Response::Success(response_data);
@@ -347,7 +529,11 @@ impl ApiAgent {
                 });
 
         trace!("Fetching file: {}", file_name);
-        let encoded = utf8_percent_encode(&file_name, NON_ALPHANUMERIC).to_string();
+        let encoded = file_name
+            .split('/')
+            .map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
 
         let request = fetch::Request::get(&format!("/songs/{}", encoded))
             .body(Nothing)
@@ -368,16 +554,28 @@ impl Response {
                 RequestType::AddSong => "Added to queue".to_owned(),
                 RequestType::PlaySong => "Playing now".to_owned(),
                 RequestType::Stop => "Player stopped".to_owned(),
+                RequestType::Pause => "Player paused".to_owned(),
+                RequestType::Resume => "Player resumed".to_owned(),
+                RequestType::Restart => "Song restarted".to_owned(),
                 RequestType::NextSong => "Next song playing".to_owned(),
                 RequestType::ClearQueue => "Queue cleared".to_owned(),
+                RequestType::RemoveEntry => "Removed from queue".to_owned(),
                 _ => "".to_owned(),
             },
             Response::Error => match request_type {
                 RequestType::AddSong => "Failed to add".to_owned(),
                 RequestType::PlaySong => "Failed to play".to_owned(),
                 RequestType::Stop => "Failed to stop player".to_owned(),
+                RequestType::Pause => "Failed to pause".to_owned(),
+                RequestType::Resume => "Failed to resume".to_owned(),
+                RequestType::Restart => "Failed to restart".to_owned(),
+                RequestType::Seek => "Failed to seek".to_owned(),
                 RequestType::NextSong => "Failed to play next".to_owned(),
                 RequestType::ClearQueue => "Failed to clear queue".to_owned(),
+                RequestType::RemoveEntry => "Failed to remove".to_owned(),
+                RequestType::MoveEntry | RequestType::SwapEntries => "Failed to move".to_owned(),
+                RequestType::SetKey => "Failed to change key".to_owned(),
+                RequestType::SetTempo => "Failed to change tempo".to_owned(),
                 _ => "".to_owned(),
             },
         };
@@ -396,6 +594,16 @@ impl Response {
     }
 }
 
+fn singer_name() -> Option<String> {
+    let storage = StorageService::new(Area::Local).ok()?;
+    let name: Result<String, Error> = storage.restore(SINGER_KEY);
+    name.ok().filter(|name| !name.trim().is_empty())
+}
+
+fn query_string<T: Serialize>(params: &T) -> String {
+    serde_urlencoded::to_string(params).unwrap()
+}
+
 impl RequestType {
     fn path(&self) -> &str {
         match self {
@@ -404,13 +612,28 @@ impl RequestType {
             RequestType::NextSong => "next",
             RequestType::ClearQueue => "clear",
             RequestType::Stop => "stop",
+            RequestType::Pause => "pause",
+            RequestType::Resume => "resume",
+            RequestType::Restart => "restart",
+            RequestType::Seek => "seek",
+            RequestType::RemoveEntry => "queue/remove",
+            RequestType::MoveEntry => "queue/move",
+            RequestType::SwapEntries => "queue/swap",
+            RequestType::SetKey => "queue/key",
+            RequestType::SetTempo => "queue/tempo",
             RequestType::AddFavorite => "favorites/add",
             RequestType::RemoveFavorite => "favorites/remove",
             RequestType::GetSongs => "songs",
             RequestType::GetArtists => "artists",
             RequestType::GetQueue => "queue",
+            RequestType::GetNowPlaying => "nowplaying",
+            RequestType::GetHistory => "history",
+            RequestType::GetStats => "stats",
+            RequestType::GetSessions => "sessions",
+            RequestType::Suggest => "suggest",
             RequestType::Config => "config",
             RequestType::PlayerNextSong => "player/next",
+            RequestType::Position => "player/position",
             RequestType::Ended => "player/ended",
             _ => "",
         }
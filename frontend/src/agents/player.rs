@@ -22,6 +22,10 @@ use yew::{
 #[derive(Serialize, Deserialize)]
 pub struct WsMessage {
     pub command: String,
+    #[serde(default)]
+    pub seconds: Option<u32>,
+    #[serde(default)]
+    pub tempo: Option<u8>,
 }
 
 pub enum Msg {
@@ -29,13 +33,17 @@ pub enum Msg {
     PlayingLoop,
     NotPlayingLoop,
     Stop,
+    Pause,
+    Resume,
+    Seek(u32),
+    Tempo(u8),
     Ended,
     GetSong,
-    FetchMp3(String),
+    FetchAudio(String),
     FetchCdg(String),
-    DecodeMp3,
+    DecodeAudio,
     DecodeError,
-    PlayMp3(AudioBuffer),
+    PlayAudio(AudioBuffer),
     StartCdgPlayer,
     ApiResponse(api::Response),
     WsReceived(Json<Result<WsMessage, Error>>),
@@ -81,8 +89,21 @@ pub struct PlayerAgent {
     buffer_source_node: Option<AudioBufferSourceNode>,
     buffer_source_node_onended: Option<EventListener>,
     playing: bool,
+    paused: bool,
+    //Queue entry of the song being played, sent with what's reported about it
+    //so the server can tell a late report from one about the next song
+    entry_id: Option<u64>,
+    //Audio context time when the song was last at `song_start_position`,
+    //counted from again whenever the tempo changes or it's seeked
     song_start_time: f64,
-    mp3: FileStatus,
+    song_start_position: f64,
+    //Speed the song plays at, which changes its pitch along with its tempo
+    playback_rate: f64,
+    //Whole seconds played when the position was last reported
+    last_reported: u64,
+    audio: FileStatus,
+    //Kept after it's played, for seeking
+    audio_buffer: Option<AudioBuffer>,
     cdg: FileStatus,
     cdg_player: Option<Cdg>,
     last_sector: f64,
@@ -111,8 +132,14 @@ impl Agent for PlayerAgent {
             buffer_source_node: None,
             buffer_source_node_onended: None,
             playing: false,
+            paused: false,
+            entry_id: None,
             song_start_time: 0.0,
-            mp3: FileStatus::None,
+            song_start_position: 0.0,
+            playback_rate: 1.0,
+            last_reported: 0,
+            audio: FileStatus::None,
+            audio_buffer: None,
             cdg: FileStatus::None,
             cdg_player: None,
             last_sector: 0.0,
@@ -156,12 +183,19 @@ impl Agent for PlayerAgent {
                     self.buffer_source_node = None;
                 };
 
-                self.mp3 = FileStatus::None;
+                self.audio = FileStatus::None;
+                self.audio_buffer = None;
                 self.cdg = FileStatus::None;
                 self.cdg_player = None;
                 self.last_sector = 0.0;
+                self.entry_id = None;
+                self.playback_rate = 1.0;
 
                 self.playing = false;
+                //The next song shouldn't start paused
+                if self.paused {
+                    self.link.callback(|_| Msg::Resume).emit(());
+                }
 
                 self.link
                     .respond(self.bridged_component.unwrap(), Response::ClearCanvas);
@@ -171,24 +205,58 @@ impl Agent for PlayerAgent {
                     self.link.callback(|_| Msg::MainLoop),
                 ));
             }
+            Msg::Pause => {
+                if let Some(context) = self.audio_context.as_ref() {
+                    if let Ok(promise) = context.suspend() {
+                        let future = JsFuture::from(promise);
+                        spawn_local(async {
+                            let _ = future.await;
+                        });
+                        self.paused = true;
+                        trace!("Audio Context suspended");
+                    }
+                }
+            }
+            Msg::Resume => {
+                if let Some(context) = self.audio_context.as_ref() {
+                    if let Ok(promise) = context.resume() {
+                        let future = JsFuture::from(promise);
+                        spawn_local(async {
+                            let _ = future.await;
+                        });
+                        self.paused = false;
+                        trace!("Audio Context resumed");
+                    }
+                }
+            }
+            Msg::Seek(secs) => {
+                if self.playing {
+                    self.seek(f64::from(secs));
+                }
+            }
+            Msg::Tempo(tempo) => {
+                self.set_tempo(tempo);
+            }
             Msg::Ended => {
                 trace!("Song ended...");
-                self.api_agent.send(api::Request::Ended);
+                if let Some(id) = self.entry_id {
+                    self.api_agent.send(api::Request::Ended(id));
+                }
                 self.link.callback(|_| Msg::Stop).emit(());
             }
             Msg::GetSong => {
                 self.api_agent.send(api::Request::PlayerNextSong);
             }
-            Msg::FetchMp3(file_name) => {
-                self.api_agent.send(api::Request::FetchMp3(file_name));
-                self.mp3 = FileStatus::Fetching;
+            Msg::FetchAudio(file_name) => {
+                self.api_agent.send(api::Request::FetchAudio(file_name));
+                self.audio = FileStatus::Fetching;
             }
             Msg::FetchCdg(file_name) => {
                 self.api_agent.send(api::Request::FetchCdg(file_name));
                 self.cdg = FileStatus::Fetching;
             }
-            Msg::DecodeMp3 => {
-                if let FileStatus::Fetched(bytes) = &self.mp3 {
+            Msg::DecodeAudio => {
+                if let FileStatus::Fetched(bytes) = &self.audio {
                     trace!("Decoding audio data...");
 
                     let clamped_array = Uint8ClampedArray::from(&bytes[..]);
@@ -198,7 +266,7 @@ impl Agent for PlayerAgent {
 
                     let promise = audio_context.decode_audio_data(&array_buffer).unwrap();
 
-                    let success_callback = self.link.callback(Msg::PlayMp3);
+                    let success_callback = self.link.callback(Msg::PlayAudio);
                     let error_callback = self.link.callback(|_| Msg::DecodeError);
 
                     spawn_local(async move {
@@ -222,7 +290,7 @@ impl Agent for PlayerAgent {
 
                 self.link.callback(|_| Msg::NotPlayingLoop).emit(());
             }
-            Msg::PlayMp3(audio) => {
+            Msg::PlayAudio(audio) => {
                 if let Some(node) = self.buffer_source_node.as_ref() {
                     node.set_buffer(Some(&audio));
 
@@ -232,11 +300,15 @@ impl Agent for PlayerAgent {
                     let destination_node: &AudioNode = destination.as_ref();
 
                     let connect_result = source_node.connect_with_audio_node(destination_node);
+                    node.playback_rate().set_value(self.playback_rate as f32);
                     let play_result = node.start();
 
                     if play_result.is_ok() && connect_result.is_ok() {
                         self.playing = true;
                         self.song_start_time = self.audio_context.as_ref().unwrap().current_time();
+                        self.song_start_position = 0.0;
+                        self.last_reported = 0;
+                        self.audio_buffer = Some(audio);
 
                         trace!("Audio is playing");
                     }
@@ -253,14 +325,21 @@ impl Agent for PlayerAgent {
                 }
             }
             Msg::ApiResponse(response) => match response {
-                api::Response::Success(api::ResponseData::PlayerNextSong { mp3, cdg }) => {
-                    self.link.callback(Msg::FetchMp3).emit(mp3);
+                api::Response::Success(api::ResponseData::PlayerNextSong {
+                    entry_id,
+                    audio,
+                    cdg,
+                    tempo,
+                }) => {
+                    self.entry_id = Some(entry_id);
+                    self.playback_rate = f64::from(tempo) / 100.0;
+                    self.link.callback(Msg::FetchAudio).emit(audio);
                     self.link.callback(Msg::FetchCdg).emit(cdg);
                 }
-                api::Response::Success(api::ResponseData::FileMp3(bytes)) => {
-                    log::trace!("Got mp3, is {} bytes", bytes.len());
-                    self.mp3 = FileStatus::Fetched(bytes);
-                    self.link.callback(|_| Msg::DecodeMp3).emit(());
+                api::Response::Success(api::ResponseData::FileAudio(bytes)) => {
+                    log::trace!("Got audio, is {} bytes", bytes.len());
+                    self.audio = FileStatus::Fetched(bytes);
+                    self.link.callback(|_| Msg::DecodeAudio).emit(());
                 }
                 api::Response::Success(api::ResponseData::FileCdg(bytes)) => {
                     log::trace!("Got cdg, is {} bytes", bytes.len());
@@ -276,6 +355,20 @@ impl Agent for PlayerAgent {
                         "stop" => {
                             self.link.callback(|_| Msg::Stop).emit(());
                         }
+                        "pause" => {
+                            self.link.callback(|_| Msg::Pause).emit(());
+                        }
+                        "resume" => {
+                            self.link.callback(|_| Msg::Resume).emit(());
+                        }
+                        "seek" => {
+                            let secs = data.seconds.unwrap_or(0);
+                            self.link.callback(move |_| Msg::Seek(secs)).emit(());
+                        }
+                        "tempo" => {
+                            let tempo = data.tempo.unwrap_or(100);
+                            self.link.callback(move |_| Msg::Tempo(tempo)).emit(());
+                        }
                         "hello" => {
                             self.link.callback(|_| Msg::MainLoop).emit(());
                         }
@@ -329,9 +422,24 @@ impl Agent for PlayerAgent {
 }
 
 impl PlayerAgent {
+    //Seconds into the song, which goes faster or slower than the audio context
+    //with the tempo
+    fn time_played(&self) -> f64 {
+        let since = self.audio_context.as_ref().unwrap().current_time() - self.song_start_time;
+        self.song_start_position + since * self.playback_rate
+    }
+
     fn playing_loop(&mut self) {
-        let time_played =
-            self.audio_context.as_ref().unwrap().current_time() - self.song_start_time;
+        let time_played = self.time_played();
+
+        //Position is reported to the server once a second, for the queue page
+        if time_played as u64 != self.last_reported {
+            self.last_reported = time_played as u64;
+            if let Some(id) = self.entry_id {
+                let elapsed = (time_played * 1000.0) as u64;
+                self.api_agent.send(api::Request::Position { id, elapsed });
+            }
+        }
 
         let calc_sector = (time_played / 0.013_333_333).floor();
 
@@ -379,22 +487,10 @@ impl PlayerAgent {
                 return;
             } else {
                 if self.buffer_source_node.is_none() {
-                    self.buffer_source_node =
-                        get_buffer_source(&self.audio_context.as_ref().unwrap());
-
-                    let callback = self.link.callback(|_| Msg::Ended);
-                    let onended = EventListener::new(
-                        &self.buffer_source_node.as_ref().unwrap().as_ref(),
-                        "ended",
-                        move |_| {
-                            callback.emit(());
-                        },
-                    );
-                    self.buffer_source_node_onended = Some(onended);
-                    trace!("Got buffer source");
+                    self.create_buffer_source();
                 }
 
-                if self.cdg == FileStatus::None && self.mp3 == FileStatus::None {
+                if self.cdg == FileStatus::None && self.audio == FileStatus::None {
                     self.link.callback(|_| Msg::GetSong).emit(());
                     trace!("Getting next song...");
                 }
@@ -407,6 +503,74 @@ impl PlayerAgent {
         ));
     }
 
+    fn create_buffer_source(&mut self) {
+        self.buffer_source_node = get_buffer_source(&self.audio_context.as_ref().unwrap());
+
+        let callback = self.link.callback(|_| Msg::Ended);
+        let onended = EventListener::new(
+            &self.buffer_source_node.as_ref().unwrap().as_ref(),
+            "ended",
+            move |_| {
+                callback.emit(());
+            },
+        );
+        self.buffer_source_node_onended = Some(onended);
+        trace!("Got buffer source");
+    }
+
+    //A buffer source only plays once, so the same audio is played again on a
+    //new one, from the new position
+    fn seek(&mut self, secs: f64) {
+        self.buffer_source_node_onended = None;
+        if let Some(node) = self.buffer_source_node.take() {
+            let _ = node.stop();
+            let _ = node.disconnect();
+        }
+        self.create_buffer_source();
+
+        let audio_context = self.audio_context.as_ref().unwrap();
+        let node = self.buffer_source_node.as_ref().unwrap();
+        node.set_buffer(self.audio_buffer.as_ref());
+
+        let source_node: &AudioNode = node;
+        let destination = audio_context.destination();
+        let destination_node: &AudioNode = destination.as_ref();
+
+        let connect_result = source_node.connect_with_audio_node(destination_node);
+        node.playback_rate().set_value(self.playback_rate as f32);
+        let play_result = node.start_with_when_and_grain_offset(0.0, secs);
+        if play_result.is_err() || connect_result.is_err() {
+            trace!("Couldn't seek, resetting player...");
+            self.link.callback(|_| Msg::Stop).emit(());
+            return;
+        }
+        self.song_start_time = audio_context.current_time();
+        self.song_start_position = secs;
+
+        //Cdg commands draw on what's already there, so the lyrics are redrawn
+        //from the start up to the new position
+        if let FileStatus::Fetched(bytes) = &self.cdg {
+            self.cdg_player = Some(Cdg::new(bytes.clone()));
+        }
+        self.last_sector = 0.0;
+
+        trace!("Audio seeked to {}s", secs);
+    }
+
+    //A tempo set before the song starts is kept for when it does. Lyrics carry
+    //on from where the song was at the change.
+    fn set_tempo(&mut self, tempo: u8) {
+        if self.playing {
+            self.song_start_position = self.time_played();
+            self.song_start_time = self.audio_context.as_ref().unwrap().current_time();
+        }
+        self.playback_rate = f64::from(tempo) / 100.0;
+        if let Some(node) = self.buffer_source_node.as_ref() {
+            node.playback_rate().set_value(self.playback_rate as f32);
+        }
+        trace!("Tempo set to {}%", tempo);
+    }
+
     fn cleanup(&mut self) {
         if let Some(node) = self.buffer_source_node.as_mut() {
             let _ = node.disconnect();
@@ -1,4 +1,9 @@
-use crate::{agents::api, components::toast_container::ToastContainer, model::Config, pages::*};
+use crate::{
+    agents::api,
+    components::{singer::SingerInput, toast_container::ToastContainer},
+    model::Config,
+    pages::*,
+};
 
 use log::trace;
 use yew::prelude::*;
@@ -18,6 +23,8 @@ pub enum AppRoute {
     Artist(u64),
     #[to = "/queue"]
     Queue,
+    #[to = "/history"]
+    History,
     #[to = "/player"]
     Player,
     #[to = "/page-not-found"]
@@ -120,6 +127,9 @@ impl Model {
                     <RouterAnchor<AppRoute> route=AppRoute::Queue
                         classes={ if current_route=="/queue" { "header__navigation-item--active" } else { "header__navigation-item" }}>
                             { "Queue" }</RouterAnchor<AppRoute>>
+                    <RouterAnchor<AppRoute> route=AppRoute::History
+                        classes={ if current_route=="/history" { "header__navigation-item--active" } else { "header__navigation-item" }}>
+                            { "History" }</RouterAnchor<AppRoute>>
                     {
                         if player_active {
                             html! {
@@ -132,6 +142,7 @@ impl Model {
                         }
                     }
                 </nav>
+                <SingerInput />
             </div>
         }
     }
@@ -153,6 +164,7 @@ impl Model {
                             AppRoute::Artist(id) => html!{<ArtistPage artist_id=id />},
                             AppRoute::Artists => html!{<ArtistsPage />},
                             AppRoute::Queue => html!{<QueuePage />},
+                            AppRoute::History => html!{<HistoryPage />},
                             AppRoute::Player => html!{<PlayerPage port_ws=port_ws fullscreen=fullscreen
                                 scale=scale disable_background=disable_background/>},
                             AppRoute::NotFound(Permissive(None)) => html!{"Page not found"},
@@ -1,3 +1,5 @@
 pub mod pagination;
+pub mod preview;
+pub mod singer;
 pub mod toast;
 pub mod toast_container;
@@ -0,0 +1,39 @@
+use yew::prelude::*;
+
+#[derive(Properties, Clone)]
+pub struct Props {
+    pub song_id: u64,
+}
+
+/// Thumbnail of a song's title screen, so guests can tell versions apart
+pub struct Preview {
+    song_id: u64,
+}
+
+impl Component for Preview {
+    type Message = ();
+    type Properties = Props;
+
+    fn create(props: Self::Properties, _: ComponentLink<Self>) -> Self {
+        Preview {
+            song_id: props.song_id,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        let changed = self.song_id != props.song_id;
+        self.song_id = props.song_id;
+        changed
+    }
+
+    fn update(&mut self, _: Self::Message) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <img class="preview" alt="" loading="lazy"
+                src=format!("/api/songs/{}/preview.png", self.song_id) />
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::model::SINGER_KEY;
+use anyhow::Error;
+use yew::{
+    prelude::*,
+    services::storage::{Area, StorageService},
+};
+
+pub enum Msg {
+    Update(String),
+}
+
+/// Name of the guest, kept in local storage so the songs they add are queued
+/// under it
+pub struct SingerInput {
+    link: ComponentLink<Self>,
+    storage: Option<StorageService>,
+    name: String,
+}
+
+impl Component for SingerInput {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let storage = StorageService::new(Area::Local).ok();
+        let name = storage
+            .as_ref()
+            .and_then(|storage| storage.restore::<Result<String, Error>>(SINGER_KEY).ok())
+            .unwrap_or_default();
+
+        SingerInput {
+            link,
+            storage,
+            name,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Update(name) => {
+                if let Some(storage) = self.storage.as_mut() {
+                    storage.store(SINGER_KEY, Ok(name.clone()));
+                }
+                self.name = name;
+            }
+        }
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <input class="input header__singer" type="text" placeholder="Your name"
+                value=&self.name
+                oninput=self.link.callback(|input: InputData| Msg::Update(input.value)) />
+        }
+    }
+}
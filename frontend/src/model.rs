@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
@@ -34,11 +34,30 @@ pub enum DataType {
     #[serde(rename = "artists")]
     Artists(Vec<Artist>),
     #[serde(rename = "queue")]
-    Queue(Vec<Song>),
+    Queue(Vec<QueueEntry>),
+    #[serde(rename = "now_playing")]
+    NowPlaying(NowPlaying),
     #[serde(rename = "next_song")]
-    PlayerNextSong { mp3: String, cdg: String },
+    PlayerNextSong {
+        entry_id: u64,
+        audio: String,
+        cdg: String,
+        #[serde(default = "normal_tempo")]
+        tempo: u8,
+    },
     #[serde(rename = "config")]
     Config(Config),
+    #[serde(rename = "suggestions")]
+    Suggestions(Vec<Suggestion>),
+    #[serde(rename = "history")]
+    History(Vec<Play>),
+    #[serde(rename = "stats")]
+    Stats {
+        songs: Vec<PlayCount>,
+        artists: Vec<PlayCount>,
+    },
+    #[serde(rename = "sessions")]
+    Sessions(Vec<Session>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,6 +67,77 @@ pub struct Song {
     pub artist_id: u64,
     pub artist_name: String,
     pub favorite: bool,
+    #[serde(default)]
+    pub library: Option<String>,
+    #[serde(default)]
+    pub duration: Option<u32>,
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub year: Option<i32>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub disc: Option<String>,
+    #[serde(default)]
+    pub track: Option<String>,
+}
+
+//Start is estimated, in seconds from the start of the first song
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueEntry {
+    pub entry_id: u64,
+    #[serde(flatten)]
+    pub song: Song,
+    #[serde(default)]
+    pub singer: Option<String>,
+    pub start_in: u32,
+    #[serde(default)]
+    pub key: i8,
+    #[serde(default = "normal_tempo")]
+    pub tempo: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum PlayerState {
+    #[serde(rename = "idle")]
+    Idle,
+    #[serde(rename = "loading")]
+    Loading,
+    #[serde(rename = "playing")]
+    Playing,
+    #[serde(rename = "paused")]
+    Paused,
+    #[serde(rename = "between_songs")]
+    BetweenSongs,
+}
+
+//Times are in seconds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NowPlaying {
+    pub state: PlayerState,
+    #[serde(default)]
+    pub entry_id: Option<u64>,
+    #[serde(default)]
+    pub song: Option<Song>,
+    #[serde(default)]
+    pub singer: Option<String>,
+    pub elapsed: u32,
+    #[serde(default)]
+    pub duration: Option<u32>,
+    #[serde(default)]
+    pub key: i8,
+    #[serde(default = "normal_tempo")]
+    pub tempo: u8,
+}
+
+//Tempos are percent of the song's normal tempo
+pub fn normal_tempo() -> u8 {
+    100
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -71,6 +161,16 @@ pub struct RequestParams {
     pub sort_direction: Option<SortDirection>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub favorites_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
 }
 
 impl Default for RequestParams {
@@ -82,15 +182,143 @@ impl Default for RequestParams {
             sort_key: None,
             sort_direction: None,
             favorites_only: None,
+            library: None,
+            genre: None,
+            year: None,
+            language: None,
+            album: None,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum SuggestionKind {
+    #[serde(rename = "artist")]
+    Artist,
+    #[serde(rename = "song")]
+    Song,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub id: u64,
+    pub name: String,
+    pub highlights: Vec<(usize, usize)>,
+    #[serde(default)]
+    pub artist_id: Option<u64>,
+    #[serde(default)]
+    pub artist_name: Option<String>,
+    #[serde(default)]
+    pub artist_highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SuggestParams {
+    pub q: String,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct PostSong {
     pub hash: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub singer: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum PlayOutcome {
+    #[serde(rename = "playing")]
+    Playing,
+    #[serde(rename = "finished")]
+    Finished,
+    #[serde(rename = "skipped")]
+    Skipped,
+}
+
+//Times are milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Play {
+    pub song_id: u64,
+    pub artist_id: u64,
+    pub title: String,
+    pub artist: String,
+    pub singer: Option<String>,
+    pub started: f64,
+    pub outcome: PlayOutcome,
+    pub completion: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayCount {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub artist_name: Option<String>,
+    pub plays: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub started: f64,
+    pub ended: f64,
+    pub songs: usize,
+    pub singers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HistoryParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+}
+
+//Songs in the queue are edited by entry id, moving one takes the position to
+//move it to, swapping takes the other song's entry id
+#[derive(Serialize, Debug, Clone)]
+pub struct PostQueueEdit {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other: Option<u64>,
+}
+
+//Semitones up or down
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostKey {
+    pub id: u64,
+    pub key: i8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostTempo {
+    pub id: u64,
+    pub tempo: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostSeek {
+    pub seconds: u32,
+}
+
+//Milliseconds into the song with entry `id`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostPosition {
+    pub id: u64,
+    pub elapsed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostEnded {
+    pub id: u64,
+}
+
+//Local storage key of the name songs are queued under
+pub const SINGER_KEY: &str = "karaoke.singer";
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
 pub enum SortKey {
     #[serde(rename = "song")]
@@ -99,6 +327,12 @@ pub enum SortKey {
     Artist,
     #[serde(rename = "numsongs")]
     NumSongs,
+    #[serde(rename = "duration")]
+    Duration,
+    #[serde(rename = "year")]
+    Year,
+    #[serde(rename = "genre")]
+    Genre,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
@@ -114,13 +348,40 @@ pub struct Config {
     pub song_path: PathBuf,
     pub data_path: PathBuf,
     pub no_collection_update: bool,
+    pub watch_collection: bool,
     pub use_web_player: bool,
     pub port: u16,
     pub port_ws: u16,
-    pub song_format: String,
+    pub song_format: SongFormat,
+    #[serde(default)]
+    pub libraries: Vec<LibraryConfig>,
+    pub artists: ArtistConfig,
     pub player: PlayerConfig,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibraryConfig {
+    pub label: String,
+    pub path: PathBuf,
+    pub format: Option<SongFormat>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SongFormat {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ArtistConfig {
+    pub fold_case: bool,
+    pub articles: Vec<String>,
+    pub featuring: Vec<String>,
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerConfig {
     pub fullscreen: bool,
@@ -1,5 +1,6 @@
 mod artist;
 mod artists;
+mod history;
 mod index;
 mod player;
 mod queue;
@@ -7,6 +8,7 @@ mod songs;
 
 pub use artist::ArtistPage;
 pub use artists::ArtistsPage;
+pub use history::HistoryPage;
 pub use index::IndexPage;
 pub use player::PlayerPage;
 pub use queue::QueuePage;
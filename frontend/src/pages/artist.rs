@@ -1,6 +1,6 @@
 use crate::{
     agents::api,
-    components::pagination::Pagination,
+    components::{pagination::Pagination, preview::Preview},
     model::{RequestParams, Song, SortDirection, SortKey},
 };
 use log::trace;
@@ -209,6 +209,7 @@ impl ArtistPage {
 
         html! {
             <tr>
+                <td class="preview-cell"><Preview song_id=song_id /></td>
                 <td>{ song.name }</td>
                 <td>
                     <button onclick=self.link.callback(move |_| Msg::Add(song_id)) class="button button-table"
@@ -238,6 +239,7 @@ impl ArtistPage {
                         <table class="table">
                             <thead>
                                 <tr>
+                                    <th></th>
                                     <th onclick=self.link.callback(|_| Msg::SortUpdate(SortKey::Song))
                                         class=self.sort_class(SortKey::Song)>{ "Song" }</th>
                                     <th></th>
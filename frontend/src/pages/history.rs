@@ -0,0 +1,290 @@
+use crate::{
+    agents::api,
+    app::AppRoute,
+    components::pagination::Pagination,
+    model::{HistoryParams, Play, PlayCount, PlayOutcome, Session},
+};
+use wasm_bindgen::JsValue;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+//Plays shown, either since the start of this month or year, everything, or one
+//night from the list of sessions
+#[derive(Clone, Copy, PartialEq)]
+pub enum Span {
+    Month,
+    Year,
+    All,
+    Night(usize),
+}
+
+pub enum Msg {
+    GetHistory,
+    SelectSpan(Span),
+    TablePageUpdate(u32),
+    ApiResponse(api::Response),
+}
+
+pub struct HistoryPage {
+    link: ComponentLink<HistoryPage>,
+    api_agent: Box<dyn Bridge<api::ApiAgent>>,
+    span: Span,
+    sessions: Vec<Session>,
+    songs: Vec<PlayCount>,
+    artists: Vec<PlayCount>,
+    plays: Vec<Play>,
+    page_selection: Option<u32>,
+    total_pages: Option<u32>,
+}
+
+impl Component for HistoryPage {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let api_agent = api::ApiAgent::bridge(link.callback(Msg::ApiResponse));
+
+        HistoryPage {
+            link,
+            api_agent,
+            span: Span::Month,
+            sessions: vec![],
+            songs: vec![],
+            artists: vec![],
+            plays: vec![],
+            page_selection: None,
+            total_pages: None,
+        }
+    }
+
+    fn mounted(&mut self) -> ShouldRender {
+        self.api_agent.send(api::Request::GetSessions);
+        self.link.send_message(Msg::GetHistory);
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::GetHistory => {
+                let (from, to) = self.span_range();
+                let params = HistoryParams {
+                    from,
+                    to,
+                    page: None,
+                };
+                self.api_agent.send(api::Request::GetStats(params.clone()));
+                self.api_agent.send(api::Request::GetHistory(HistoryParams {
+                    page: self.page_selection,
+                    ..params
+                }));
+            }
+            Msg::SelectSpan(span) => {
+                self.span = span;
+                self.page_selection = None;
+                self.update(Msg::GetHistory);
+                return true;
+            }
+            Msg::TablePageUpdate(n) => {
+                self.page_selection = Some(n);
+                self.update(Msg::GetHistory);
+            }
+            Msg::ApiResponse(response) => match response {
+                api::Response::Success(api::ResponseData::Sessions(sessions)) => {
+                    self.sessions = sessions;
+                    return true;
+                }
+                api::Response::Success(api::ResponseData::Stats { songs, artists }) => {
+                    self.songs = songs;
+                    self.artists = artists;
+                    return true;
+                }
+                api::Response::Success(api::ResponseData::History { plays, total_pages }) => {
+                    self.plays = plays;
+                    self.total_pages = Some(total_pages);
+                    return true;
+                }
+                _ => {}
+            },
+        }
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div>
+                <div class="history__spans">
+                    { self.view_span_button("This Month", Span::Month) }
+                    { self.view_span_button("This Year", Span::Year) }
+                    { self.view_span_button("All Time", Span::All) }
+                </div>
+                <div class="history__columns">
+                    { self.view_counts("Top Songs", &self.songs, false) }
+                    { self.view_counts("Top Artists", &self.artists, true) }
+                    { self.view_sessions() }
+                </div>
+                { self.view_plays() }
+            </div>
+        }
+    }
+}
+
+impl HistoryPage {
+    fn span_range(&self) -> (Option<u64>, Option<u64>) {
+        match self.span {
+            Span::Month | Span::Year => {
+                let date = js_sys::Date::new_0();
+                if self.span == Span::Year {
+                    date.set_month(0);
+                }
+                date.set_date(1);
+                date.set_hours(0);
+                date.set_minutes(0);
+                date.set_seconds(0);
+                date.set_milliseconds(0);
+                (Some(date.get_time() as u64), None)
+            }
+            Span::All => (None, None),
+            Span::Night(idx) => match self.sessions.get(idx) {
+                Some(session) => (Some(session.started as u64), Some(session.ended as u64 + 1)),
+                None => (None, None),
+            },
+        }
+    }
+
+    fn view_span_button(&self, label: &str, span: Span) -> Html {
+        let class = if self.span == span {
+            "button button-queue-action history__span--active"
+        } else {
+            "button button-queue-action"
+        };
+
+        html! {
+            <button class=class role="button" onclick=self.link.callback(move |_| Msg::SelectSpan(span))>
+                { label }
+            </button>
+        }
+    }
+
+    fn view_counts(&self, title: &str, counts: &[PlayCount], artists: bool) -> Html {
+        html! {
+            <div class="history__column">
+                <h3>{ title }</h3>
+                <table class="table">
+                    <tbody>
+                        {
+                            for counts.iter().map(|count| {
+                                let name = if artists {
+                                    html! {
+                                        <RouterAnchor<AppRoute> route=AppRoute::Artist(count.id) classes="artist-link">{ &count.name }</RouterAnchor<AppRoute>>
+                                    }
+                                } else {
+                                    html! {
+                                        <span>{ &count.name }<span class="history__artist">{ count.artist_name.clone().unwrap_or_default() }</span></span>
+                                    }
+                                };
+                                html! {
+                                    <tr>
+                                        <td>{ name }</td>
+                                        <td class="text-center">{ count.plays }</td>
+                                    </tr>
+                                }
+                            })
+                        }
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+
+    //Nights played, selecting one shows only what was sung that night
+    fn view_sessions(&self) -> Html {
+        html! {
+            <div class="history__column">
+                <h3>{ "Nights" }</h3>
+                <table class="table">
+                    <tbody>
+                        {
+                            for self.sessions.iter().enumerate().map(|(idx, session)| {
+                                let class = if self.span == Span::Night(idx) {
+                                    "history__session history__session--active"
+                                } else {
+                                    "history__session"
+                                };
+                                html! {
+                                    <tr class=class onclick=self.link.callback(move |_| Msg::SelectSpan(Span::Night(idx)))>
+                                        <td>{ format!("{} {}-{}", view_date(session.started), view_time(session.started), view_time(session.ended)) }</td>
+                                        <td class="text-center">{ format!("{} songs", session.songs) }</td>
+                                        <td class="text-center">{ format!("{} singers", session.singers) }</td>
+                                    </tr>
+                                }
+                            })
+                        }
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+
+    fn view_plays(&self) -> Html {
+        html! {
+            <div>
+                <table class="table">
+                    <thead>
+                        <tr>
+                            <th>{ "Played" }</th>
+                            <th>{ "Singer" }</th>
+                            <th>{ "Song" }</th>
+                            <th class="text-center">{ "Artist" }</th>
+                            <th class="text-center">{ "" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {
+                            for self.plays.iter().map(|play| {
+                                html! {
+                                    <tr>
+                                        <td>{ format!("{} {}", view_date(play.started), view_time(play.started)) }</td>
+                                        <td>{ play.singer.clone().unwrap_or_default() }</td>
+                                        <td>{ &play.title }</td>
+                                        <td class="text-center">
+                                            <RouterAnchor<AppRoute> route=AppRoute::Artist(play.artist_id) classes="artist-link">{ &play.artist }</RouterAnchor<AppRoute>>
+                                        </td>
+                                        <td class="text-center">{ view_outcome(play) }</td>
+                                    </tr>
+                                }
+                            })
+                        }
+                    </tbody>
+                </table>
+                <Pagination onupdate=self.link.callback(Msg::TablePageUpdate)
+                                current_page={ self.page_selection.unwrap_or(1) }
+                                total_pages={ self.total_pages.unwrap_or(0) }
+                />
+            </div>
+        }
+    }
+}
+
+fn view_outcome(play: &Play) -> String {
+    match (play.outcome, play.completion) {
+        (PlayOutcome::Playing, _) => "Playing".to_owned(),
+        (PlayOutcome::Finished, _) => "Finished".to_owned(),
+        (PlayOutcome::Skipped, Some(completion)) => format!("Skipped at {:.0}%", completion),
+        (PlayOutcome::Skipped, None) => "Skipped".to_owned(),
+    }
+}
+
+fn view_date(time: f64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(time));
+    format!(
+        "{}-{:02}-{:02}",
+        date.get_full_year(),
+        date.get_month() + 1,
+        date.get_date()
+    )
+}
+
+fn view_time(time: f64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(time));
+    format!("{}:{:02}", date.get_hours(), date.get_minutes())
+}
@@ -1,6 +1,12 @@
-use crate::{agents::api, model::Song};
+use crate::{
+    agents::api,
+    components::preview::Preview,
+    model::{NowPlaying, PlayerState, QueueEntry, Song},
+};
 use log::trace;
 use std::time::Duration;
+use wasm_bindgen::JsCast;
+use web_sys::Element;
 use yew::{
     prelude::*,
     services::{IntervalService, Task},
@@ -10,15 +16,31 @@ pub enum Msg {
     Clear,
     Stop,
     Next,
+    Pause,
+    Resume,
+    Restart,
+    Seek(u32),
     GetQueue,
     Favorite((bool, u64)),
+    Remove(u64),
+    Swap((u64, u64)),
+    Key((u64, i8)),
+    Tempo((u64, u8)),
+    DragStart(u64),
+    Drop(usize),
+    DragEnd,
     ApiResponse(api::Response),
 }
 
 pub struct QueuePage {
     link: ComponentLink<QueuePage>,
     api_agent: Box<dyn Bridge<api::ApiAgent>>,
-    queue: Vec<Song>,
+    queue: Vec<QueueEntry>,
+    now_playing: Option<NowPlaying>,
+    //Only the native player can change key
+    keys: bool,
+    //Entry id of the row being dragged
+    dragging: Option<u64>,
     #[allow(dead_code)]
     job: Box<dyn Task>,
 }
@@ -38,11 +60,15 @@ impl Component for QueuePage {
             link,
             api_agent,
             queue: vec![],
+            now_playing: None,
+            keys: false,
+            dragging: None,
             job: Box::new(handle),
         }
     }
 
     fn mounted(&mut self) -> ShouldRender {
+        self.api_agent.send(api::Request::Config);
         self.link.send_message(Msg::GetQueue);
         false
     }
@@ -64,8 +90,29 @@ impl Component for QueuePage {
                 self.api_agent.send(api::Request::NextSong);
                 self.update(Msg::GetQueue);
             }
+            Msg::Pause => {
+                trace!("Pausing player");
+                self.api_agent.send(api::Request::Pause);
+                self.update(Msg::GetQueue);
+            }
+            Msg::Resume => {
+                trace!("Resuming player");
+                self.api_agent.send(api::Request::Resume);
+                self.update(Msg::GetQueue);
+            }
+            Msg::Restart => {
+                trace!("Restarting song");
+                self.api_agent.send(api::Request::Restart);
+                self.update(Msg::GetQueue);
+            }
+            Msg::Seek(secs) => {
+                trace!("Seeking to {}s", secs);
+                self.api_agent.send(api::Request::Seek(secs));
+                self.update(Msg::GetQueue);
+            }
             Msg::GetQueue => {
                 self.api_agent.send(api::Request::GetQueue);
+                self.api_agent.send(api::Request::GetNowPlaying);
             }
             Msg::Favorite((favorite, id)) => {
                 if favorite {
@@ -75,12 +122,52 @@ impl Component for QueuePage {
                 }
                 self.update(Msg::GetQueue);
             }
-            Msg::ApiResponse(response) => {
-                if let api::Response::Success(api::ResponseData::Queue(queue)) = response {
+            Msg::Remove(id) => {
+                trace!("Removing queue entry {}", id);
+                self.api_agent.send(api::Request::RemoveEntry(id));
+                self.update(Msg::GetQueue);
+            }
+            Msg::Swap((id, other)) => {
+                self.api_agent.send(api::Request::SwapEntries(id, other));
+                self.update(Msg::GetQueue);
+            }
+            Msg::Key((id, key)) => {
+                self.api_agent.send(api::Request::SetKey { id, key });
+                self.update(Msg::GetQueue);
+            }
+            Msg::Tempo((id, tempo)) => {
+                self.api_agent.send(api::Request::SetTempo { id, tempo });
+                self.update(Msg::GetQueue);
+            }
+            Msg::DragStart(id) => {
+                self.dragging = Some(id);
+            }
+            Msg::Drop(position) => {
+                if let Some(id) = self.dragging.take() {
+                    trace!("Moving queue entry {} to {}", id, position);
+                    self.api_agent
+                        .send(api::Request::MoveEntry { id, position });
+                    self.update(Msg::GetQueue);
+                }
+            }
+            Msg::DragEnd => {
+                self.dragging = None;
+            }
+            Msg::ApiResponse(response) => match response {
+                api::Response::Success(api::ResponseData::Queue(queue)) => {
                     self.queue = queue;
                     return true;
                 }
-            }
+                api::Response::Success(api::ResponseData::NowPlaying(now_playing)) => {
+                    self.now_playing = Some(now_playing);
+                    return true;
+                }
+                api::Response::Success(api::ResponseData::Config(config)) => {
+                    self.keys = !config.use_web_player;
+                    return true;
+                }
+                _ => {}
+            },
         }
         false
     }
@@ -96,6 +183,7 @@ impl Component for QueuePage {
                     <button class="button button-queue-action"
                         role="button" aria-pressed="true" onclick=self.link.callback(|_| Msg::Stop)>{ "Stop" }</button>
                 </div>
+                { self.view_now_playing() }
                 { self.view_table() }
             </div>
         }
@@ -103,23 +191,135 @@ impl Component for QueuePage {
 }
 
 impl QueuePage {
-    fn view_row(&self, idx: usize, song: Song) -> Html {
+    //Rows are edited by entry id rather than position, so an edit made while
+    //the queue changes underneath still applies to the song that was shown
+    fn view_row(&self, idx: usize, entry: QueueEntry) -> Html {
+        let song: Song = entry.song;
         let song_id = song.id;
         let favorite = song.favorite;
+        let entry_id = entry.entry_id;
+        let above = idx.checked_sub(1).and_then(|idx| self.queue.get(idx));
+        let below = self.queue.get(idx + 1);
+        let dragging = if self.dragging == Some(entry_id) {
+            "queue__row--dragging"
+        } else {
+            ""
+        };
 
         html! {
-            <tr>
-                <th class="text-center">{ idx + 1 }</th>
+            <tr class=dragging draggable="true"
+                ondragstart=self.link.callback(move |e: DragEvent| {
+                    if let Some(data) = e.data_transfer() {
+                        let _ = data.set_data("text/plain", &entry_id.to_string());
+                    }
+                    Msg::DragStart(entry_id)
+                })
+                ondragover=self.link.batch_callback(|e: DragEvent| {
+                    e.prevent_default();
+                    vec![]
+                })
+                ondrop=self.link.callback(move |e: DragEvent| {
+                    e.prevent_default();
+                    Msg::Drop(idx)
+                })
+                ondragend=self.link.callback(|_| Msg::DragEnd)>
+                <th class="text-center queue__handle">{ idx + 1 }</th>
+                <td class="preview-cell"><Preview song_id=song_id /></td>
+                <td>{ entry.singer.unwrap_or_default() }</td>
                 <td>{ song.name }</td>
                 <td class="text-center">{ song.artist_name }</td>
+                <td class="text-center">{ view_start_time(entry.start_in) }</td>
+                { if self.keys { html! { <td class="text-center">{ self.view_key(entry_id, entry.key) }</td> } } else { html! {} } }
+                <td class="text-center">{ self.view_tempo(entry_id, entry.tempo) }</td>
                 <td class="heart-center">
                     <button onclick=self.link.callback(move |_| Msg::Favorite((favorite, song_id))) class="button button-table"
                         role="button" aria-pressed="true">{ self.view_favorite(favorite) }</button>
                 </td>
+                <td class="queue__edit">
+                    { self.view_swap_button("▲", entry_id, above) }
+                    { self.view_swap_button("▼", entry_id, below) }
+                    <button onclick=self.link.callback(move |_| Msg::Remove(entry_id)) class="button button-table"
+                        role="button" aria-pressed="true" title="Remove">{ "✕" }</button>
+                </td>
             </tr>
         }
     }
 
+    fn view_now_playing(&self) -> Html {
+        let now_playing = match &self.now_playing {
+            Some(now_playing) => now_playing,
+            None => return html! {},
+        };
+        let song = match &now_playing.song {
+            Some(song) => song,
+            None => return html! {},
+        };
+        let state = match now_playing.state {
+            PlayerState::Loading => "Loading",
+            PlayerState::Paused => "Paused",
+            PlayerState::BetweenSongs => "Stopping",
+            _ => "Now Playing",
+        };
+        let pause = if now_playing.state == PlayerState::Paused {
+            html! {
+                <button class="button button-table" role="button" aria-pressed="true"
+                    onclick=self.link.callback(|_| Msg::Resume)>{ "Resume" }</button>
+            }
+        } else {
+            html! {
+                <button class="button button-table" role="button" aria-pressed="true"
+                    onclick=self.link.callback(|_| Msg::Pause)>{ "Pause" }</button>
+            }
+        };
+        //Clicking the bar seeks to that point of the song
+        let duration = now_playing.duration.unwrap_or(0);
+        let onseek = self.link.batch_callback(move |e: MouseEvent| {
+            let width = e
+                .current_target()
+                .and_then(|target| target.dyn_into::<Element>().ok())
+                .map(|element| element.client_width())
+                .unwrap_or(0);
+            if duration == 0 || width <= 0 {
+                return vec![];
+            }
+            let fraction = (f64::from(e.offset_x()) / f64::from(width))
+                .max(0.0)
+                .min(1.0);
+            vec![Msg::Seek((fraction * f64::from(duration)) as u32)]
+        });
+        //Songs without a known length show how long they've played for
+        let (progress, time) = match now_playing.duration {
+            Some(duration) if duration > 0 => (
+                (f64::from(now_playing.elapsed) / f64::from(duration) * 100.0).min(100.0),
+                format!(
+                    "{} / {}",
+                    view_duration(now_playing.elapsed),
+                    view_duration(duration)
+                ),
+            ),
+            _ => (0.0, view_duration(now_playing.elapsed)),
+        };
+
+        html! {
+            <div class="queue__now-playing">
+                <div class="queue__now-playing-song">
+                    <span class="queue__now-playing-state">{ state }</span>
+                    <span>{ format!("{} - {}", song.name, song.artist_name) }</span>
+                    <span class="queue__now-playing-singer">{ now_playing.singer.clone().unwrap_or_default() }</span>
+                    <span class="queue__now-playing-time">{ time }</span>
+                    { now_playing.entry_id.map(|id| self.view_key(id, now_playing.key)).unwrap_or_default() }
+                    { now_playing.entry_id.map(|id| self.view_tempo(id, now_playing.tempo)).unwrap_or_default() }
+                    { pause }
+                    <button class="button button-table" role="button" aria-pressed="true"
+                        onclick=self.link.callback(|_| Msg::Restart)>{ "Restart" }</button>
+                </div>
+                <div class="queue__progress" onclick=onseek>
+                    <div class="queue__progress-bar" style=format!("width: {}%", progress)></div>
+                </div>
+            </div>
+        }
+    }
+
     fn view_table(&self) -> Html {
         html! {
             <div>
@@ -128,15 +328,21 @@ impl QueuePage {
                         <thead>
                             <tr>
                                 <th class="text-center">{ "#" }</th>
+                                <th></th>
+                                <th>{ "Singer" }</th>
                                 <th>{ "Song" }</th>
                                 <th class="text-center">{ "Artist" }</th>
+                                <th class="text-center">{ "Starts" }</th>
+                                { if self.keys { html! { <th class="text-center">{ "Key" }</th> } } else { html! {} } }
+                                <th class="text-center">{ "Tempo" }</th>
                                 <th><div class="heart-header heart-center">{ "🤍" }</div></th>
+                                <th></th>
                             </tr>
                         </thead>
                         <tbody>
                             {
-                                for self.queue.iter().enumerate().map(|(idx, song)| {
-                                    self.view_row(idx, song.clone())
+                                for self.queue.iter().enumerate().map(|(idx, entry)| {
+                                    self.view_row(idx, entry.clone())
                                 })
                             }
                         </tbody>
@@ -146,6 +352,62 @@ impl QueuePage {
         }
     }
 
+    //Semitones up or down, ie. "♭ -2 ♯"
+    fn view_key(&self, entry_id: u64, key: i8) -> Html {
+        if !self.keys {
+            return html! {};
+        }
+        let label = if key > 0 {
+            format!("+{}", key)
+        } else {
+            key.to_string()
+        };
+        let lowest = key <= -MAX_KEY;
+        let highest = key >= MAX_KEY;
+
+        html! {
+            <span class="queue__key">
+                <button onclick=self.link.callback(move |_| Msg::Key((entry_id, key - 1))) class="button button-table"
+                    role="button" aria-pressed="true" title="Key down" disabled=lowest>{ "♭" }</button>
+                <span class="queue__key-label">{ label }</span>
+                <button onclick=self.link.callback(move |_| Msg::Key((entry_id, key + 1))) class="button button-table"
+                    role="button" aria-pressed="true" title="Key up" disabled=highest>{ "♯" }</button>
+            </span>
+        }
+    }
+
+    //Percent of the normal tempo, ie. "− 95% +"
+    fn view_tempo(&self, entry_id: u64, tempo: u8) -> Html {
+        let slowest = tempo <= MIN_TEMPO;
+        let fastest = tempo >= MAX_TEMPO;
+
+        html! {
+            <span class="queue__tempo">
+                <button onclick=self.link.callback(move |_| Msg::Tempo((entry_id, tempo - TEMPO_STEP))) class="button button-table"
+                    role="button" aria-pressed="true" title="Slower" disabled=slowest>{ "−" }</button>
+                <span class="queue__key-label">{ format!("{}%", tempo) }</span>
+                <button onclick=self.link.callback(move |_| Msg::Tempo((entry_id, tempo + TEMPO_STEP))) class="button button-table"
+                    role="button" aria-pressed="true" title="Faster" disabled=fastest>{ "+" }</button>
+            </span>
+        }
+    }
+
+    //Moves a song up or down by swapping it with its neighbour
+    fn view_swap_button(&self, label: &str, entry_id: u64, other: Option<&QueueEntry>) -> Html {
+        match other {
+            Some(other) => {
+                let other_id = other.entry_id;
+                html! {
+                    <button onclick=self.link.callback(move |_| Msg::Swap((entry_id, other_id))) class="button button-table"
+                        role="button" aria-pressed="true">{ label }</button>
+                }
+            }
+            None => html! {
+                <button class="button button-table" role="button" disabled=true>{ label }</button>
+            },
+        }
+    }
+
     fn view_favorite(&self, favorite: bool) -> &str {
         if favorite {
             "♥️"
@@ -154,3 +416,24 @@ impl QueuePage {
         }
     }
 }
+
+//Furthest the native player shifts a song's key, in semitones either way
+const MAX_KEY: i8 = 6;
+
+//Slowest and fastest a song can be played, in percent of its normal tempo.
+//The web player changes pitch with tempo, the native player keeps it.
+const MIN_TEMPO: u8 = 80;
+const MAX_TEMPO: u8 = 120;
+const TEMPO_STEP: u8 = 5;
+
+//Seconds as "3:05"
+fn view_duration(secs: u32) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+//Estimated clock time a song starts, ie. "~21:05"
+fn view_start_time(start_in: u32) -> String {
+    let date = js_sys::Date::new_0();
+    date.set_time(date.get_time() + f64::from(start_in) * 1000.0);
+    format!("~{}:{:02}", date.get_hours(), date.get_minutes())
+}
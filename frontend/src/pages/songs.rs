@@ -1,8 +1,8 @@
 use crate::{
     agents::api,
     app::AppRoute,
-    components::pagination::Pagination,
-    model::{RequestParams, Song, SortDirection, SortKey},
+    components::{pagination::Pagination, preview::Preview},
+    model::{RequestParams, Song, SortDirection, SortKey, Suggestion, SuggestionKind},
 };
 use log::trace;
 use yew::prelude::*;
@@ -15,6 +15,8 @@ pub enum Msg {
     TablePageUpdate(u32),
     SortUpdate(SortKey),
     Search(String),
+    AddSuggestion(u64),
+    CloseSuggestions,
     Favorite((bool, u64)),
     ApiResponse(api::Response),
 }
@@ -29,6 +31,7 @@ pub struct SongsPage {
     api_agent: Box<dyn Bridge<api::ApiAgent>>,
     songs_fetched: bool,
     songs: Vec<Song>,
+    suggestions: Vec<Suggestion>,
     search: Option<String>,
     page_selection: Option<u32>,
     total_pages: Option<u32>,
@@ -48,6 +51,7 @@ impl Component for SongsPage {
             link,
             api_agent,
             songs: vec![],
+            suggestions: vec![],
             songs_fetched: false,
             search: None,
             page_selection: None,
@@ -69,6 +73,7 @@ impl Component for SongsPage {
         self.link.send_message(Msg::GetSongs);
 
         self.search = None;
+        self.suggestions = vec![];
         self.page_selection = None;
         self.sort_key = None;
         self.sort_direction = None;
@@ -118,9 +123,24 @@ impl Component for SongsPage {
             }
             Msg::Search(value) => {
                 trace!("Search Input: {}", value);
+                if value.trim().is_empty() {
+                    self.suggestions = vec![];
+                } else {
+                    self.api_agent.send(api::Request::Suggest(value.clone()));
+                }
                 self.search = Some(value);
                 self.page_selection = None;
                 self.update(Msg::GetSongs);
+                return true;
+            }
+            Msg::AddSuggestion(id) => {
+                self.suggestions = vec![];
+                self.update(Msg::Add(id));
+                return true;
+            }
+            Msg::CloseSuggestions => {
+                self.suggestions = vec![];
+                return true;
             }
             Msg::Favorite((favorite, id)) => {
                 if favorite {
@@ -130,16 +150,22 @@ impl Component for SongsPage {
                 }
                 self.update(Msg::GetSongs);
             }
-            Msg::ApiResponse(response) => {
-                if let api::Response::Success(api::ResponseData::Songs { songs, total_pages }) =
-                    response
-                {
+            Msg::ApiResponse(response) => match response {
+                api::Response::Success(api::ResponseData::Songs { songs, total_pages }) => {
                     self.songs = songs;
                     self.total_pages = Some(total_pages);
                     self.songs_fetched = true;
                     return true;
                 }
-            }
+                api::Response::Success(api::ResponseData::Suggestions(suggestions)) => {
+                    //The search may have been cleared while they were fetched
+                    if self.search_value().is_some() {
+                        self.suggestions = suggestions;
+                        return true;
+                    }
+                }
+                _ => {}
+            },
         }
         false
     }
@@ -196,10 +222,12 @@ impl SongsPage {
 
         html! {
             <tr>
+                <td class="preview-cell"><Preview song_id=song_id /></td>
                 <td>{ song.name }</td>
                 <td>
                     <RouterAnchor<AppRoute> route=AppRoute::Artist(song.artist_id) classes="artist-link">{ song.artist_name }</ RouterAnchor<AppRoute>>
                 </td>
+                <td>{ view_duration(song.duration) }</td>
                 <td>
                     <button onclick=self.link.callback(move |_| Msg::Add(song_id)) class="button button-table"
                         role="button" aria-pressed="true">{ "Add" }</button>
@@ -220,18 +248,25 @@ impl SongsPage {
         if self.songs_fetched {
             html! {
                 <div>
-                    <div>
+                    <div class="suggest">
                         <input class="input" type="text" placeholder="Search"
-                            oninput=self.link.callback(|input: InputData| Msg::Search(input.value))></input>
+                            oninput=self.link.callback(|input: InputData| Msg::Search(input.value))
+                            onkeydown=self.link.batch_callback(|e: KeyboardEvent| {
+                                if e.key() == "Escape" { vec![Msg::CloseSuggestions] } else { vec![] }
+                            })></input>
+                        { self.view_suggestions() }
                     </div>
                     <div>
                         <table class="table">
                             <thead>
                                 <tr>
+                                    <th></th>
                                     <th onclick=self.link.callback(|_| Msg::SortUpdate(SortKey::Song))
                                         class=self.sort_class(SortKey::Song)>{ "Song" }</th>
                                     <th onclick=self.link.callback(|_| Msg::SortUpdate(SortKey::Artist))
                                         class=self.sort_class(SortKey::Artist)>{ "Artist" }</th>
+                                    <th onclick=self.link.callback(|_| Msg::SortUpdate(SortKey::Duration))
+                                        class=self.sort_class(SortKey::Duration)>{ "Length" }</th>
                                     <th></th>
                                     <th></th>
                                     <th><div class="heart-header heart-center">{ "🤍" }</div></th>
@@ -257,6 +292,46 @@ impl SongsPage {
         }
     }
 
+    fn view_suggestions(&self) -> Html {
+        if self.suggestions.is_empty() {
+            return html! {};
+        }
+
+        html! {
+            <ul class="suggest__list">
+                { for self.suggestions.iter().map(|suggestion| self.view_suggestion(suggestion)) }
+            </ul>
+        }
+    }
+
+    //Picking an artist opens their page, picking a song adds it to the queue
+    fn view_suggestion(&self, suggestion: &Suggestion) -> Html {
+        let name = view_highlighted(&suggestion.name, &suggestion.highlights);
+
+        match suggestion.kind {
+            SuggestionKind::Artist => html! {
+                <li class="suggest__item">
+                    <RouterAnchor<AppRoute> route=AppRoute::Artist(suggestion.id) classes="suggest__link">
+                        <span class="suggest__kind">{ "Artist" }</span>
+                        { name }
+                    </ RouterAnchor<AppRoute>>
+                </li>
+            },
+            SuggestionKind::Song => {
+                let song_id = suggestion.id;
+                let artist_name = suggestion.artist_name.as_deref().unwrap_or_default();
+
+                html! {
+                    <li class="suggest__item" onclick=self.link.callback(move |_| Msg::AddSuggestion(song_id))>
+                        <span class="suggest__kind">{ "Song" }</span>
+                        { name }
+                        <span class="suggest__artist">{ view_highlighted(artist_name, &suggestion.artist_highlights) }</span>
+                    </li>
+                }
+            }
+        }
+    }
+
     fn view_favorite(&self, favorite: bool) -> &str {
         if favorite {
             "♥️"
@@ -265,3 +340,30 @@ impl SongsPage {
         }
     }
 }
+
+//Song length as m:ss, blank when unknown
+fn view_duration(duration: Option<u32>) -> String {
+    match duration {
+        Some(secs) => format!("{}:{:02}", secs / 60, secs % 60),
+        None => String::new(),
+    }
+}
+
+//Text with the matched byte ranges marked
+fn view_highlighted(text: &str, highlights: &[(usize, usize)]) -> Html {
+    let mut nodes = vec![];
+    let mut last = 0;
+    for &(start, end) in highlights {
+        match (text.get(last..start), text.get(start..end)) {
+            (Some(before), Some(matched)) => {
+                nodes.push(html! { { before } });
+                nodes.push(html! { <mark>{ matched }</mark> });
+                last = end;
+            }
+            _ => break,
+        }
+    }
+    nodes.push(html! { { &text[last..] } });
+
+    html! { <span>{ for nodes.into_iter() }</span> }
+}
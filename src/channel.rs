@@ -1,5 +1,5 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
-use karaoke::collection::Kfile;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use karaoke::{collection::Kfile, queue::QueueEntry};
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -7,12 +7,10 @@ lazy_static! {
         let (player_send, player_receive) = bounded(1);
         (player_send, player_receive)
     };
-    pub static ref PLAYER_CHANNEL: (Sender<PlayerCommand>, Receiver<PlayerCommand>) = {
-        let (player_send, player_receive) = bounded(1);
-        (player_send, player_receive)
-    };
+    //Unbounded so no command is lost while the player catches up, the playback
+    //has already moved on by the time it's sent
     pub static ref LIVE_CHANNEL: (Sender<LiveCommand>, Receiver<LiveCommand>) = {
-        let (live_send, live_receive) = bounded(1);
+        let (live_send, live_receive) = unbounded();
         (live_send, live_receive)
     };
 }
@@ -23,17 +21,26 @@ pub enum WorkerCommand {
     Next,
     PlayNow { kfile: Kfile },
     ClearQueue,
-    AddQueue { kfile: Kfile },
-}
-
-#[derive(Eq, PartialEq, Debug)]
-pub enum PlayerCommand {
-    Play { kfile: Kfile },
+    AddQueue { entry: QueueEntry },
+    RemoveQueue { id: u64 },
+    MoveQueue { id: u64, position: usize },
+    SwapQueue { id: u64, other: u64 },
+    Pause,
+    Resume,
+    Seek { secs: u32 },
+    Restart,
+    SetKey { id: u64, key: i8 },
+    SetTempo { id: u64, tempo: u8 },
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum LiveCommand {
-    Stop, //
+    Stop,
+    Pause,
+    Resume,
+    Seek { secs: u32 },
+    Key { key: i8 },
+    Tempo { tempo: u8 },
 }
 
 #[derive(Eq, PartialEq, Debug)]
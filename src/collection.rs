@@ -1,23 +1,37 @@
+use claxon::FlacReader;
+use failure::{bail, format_err};
 use glob::glob;
 use id3::Tag;
-use karaoke::{log_error, CONFIG};
+use karaoke::{
+    config::{ArtistConfig, Library, SongFormat},
+    log_error,
+    search::SearchIndex,
+    store::{CollectionDB, FavoritesDB},
+    CONFIG,
+};
 use lazy_static::lazy_static;
+use lewton::inside_ogg::OggStreamReader;
 use rayon::prelude::*;
+use regex::Regex;
 use rustbreak::{deser::Yaml, FileDatabase};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    collections::{HashMap, HashSet},
     default::Default,
-    hash::{Hash, Hasher},
-    path::PathBuf,
+    fs::{metadata, File},
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
     result::Result,
+    sync::{Arc, RwLock},
+    time::UNIX_EPOCH,
 };
+use zip::ZipArchive;
 
 lazy_static! {
-    pub static ref COLLECTION: Collection = {
+    pub static ref COLLECTION: Arc<RwLock<Collection>> = {
         let collection = startup(CONFIG.no_collection_update);
         match collection {
-            Ok(c) => c,
+            Ok(c) => Arc::from(RwLock::from(c)),
             Err(e) => {
                 log_error(&e);
                 std::process::exit(1);
@@ -26,128 +40,151 @@ lazy_static! {
     };
 }
 
-pub type CollectionDB = FileDatabase<HashMap<u64, Kfile>, Yaml>;
-pub type FavoritesDB = FileDatabase<HashSet<u64>, Yaml>;
+pub type OverridesDB = FileDatabase<Overrides, Yaml>;
 
 pub trait Database {
     type Data;
 
     fn initialize(path: &PathBuf) -> Result<Box<Self>, failure::Error>;
-    fn refresh(&self, path: &PathBuf) -> Result<(), failure::Error>;
+    fn refresh(&self, libraries: &[Library]) -> Result<RefreshSummary, failure::Error>;
     fn data(&self) -> Result<Self::Data, failure::Error>;
 }
 
 impl Database for CollectionDB {
-    type Data = Collection;
+    type Data = Vec<Kfile>;
 
-    //If file doesn't exist, create default. Load db from file.
+    //Opens the store in the data dir, importing an old db.yaml into it
     fn initialize(path: &PathBuf) -> Result<Box<CollectionDB>, failure::Error> {
-        let db: CollectionDB;
-
-        let mut db_path = path.to_path_buf();
-        db_path.push("db.yaml");
-
-        let exists = db_path.exists();
-        db = CollectionDB::from_path(db_path, HashMap::new())?;
-        if !exists {
-            db.save()?;
-        }
-        db.load()?;
-
-        Ok(Box::new(db))
+        Ok(Box::new(CollectionDB::open(path)?))
     }
 
-    fn refresh(&self, song_path: &PathBuf) -> Result<(), failure::Error> {
-        let cdg_files = all_cdg(&song_path);
-        let valid = valid_cdg_mp3_paths(cdg_files);
-
-        let mut existing_keys = Vec::new();
-        self.read(|db| {
-            for key in db.keys() {
-                existing_keys.push(key.clone());
+    //Only songs whose files changed size or modified time since the last refresh
+    //are parsed again. Everything is keyed by source path, so this stays linear
+    //with the size of the collection.
+    fn refresh(&self, libraries: &[Library]) -> Result<RefreshSummary, failure::Error> {
+        let parsers = libraries
+            .iter()
+            .map(|library| SongParser::new(&library.format))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        //A file is only scanned once, even if it sits in nested libraries
+        let mut seen = HashSet::new();
+        let mut sources: Vec<(SongSource, &Library, &SongParser)> = Vec::new();
+        for (library, parser) in libraries.iter().zip(parsers.iter()) {
+            for source in library_sources(library) {
+                if seen.insert(source.path().to_path_buf()) {
+                    sources.push((source, library, parser));
+                }
             }
-        })?;
-
-        let valid_kfiles = valid
-            .par_iter()
-            .map(|path| Kfile::new(path, &CONFIG.song_format))
-            .collect::<Vec<Kfile>>();
+        }
 
-        let missing_valid_keys_to_remove: Vec<u64> = existing_keys
-            .par_iter()
-            .filter_map(|k| {
-                let valid_keys: Vec<u64> = valid_kfiles
-                    .par_iter()
-                    .map(|x| calculate_hash(&x))
-                    .collect();
-                if valid_keys.contains(&k) {
-                    None
-                } else {
-                    Some(*k)
-                }
-            })
-            .collect();
+        //Source path -> (key, stamp) of every song already in the db. Duplicate
+        //entries for the same source are dropped.
+        let mut existing: HashMap<PathBuf, (u64, Option<FileStamp>)> = HashMap::new();
+        let mut duplicate_keys = Vec::new();
+        for kfile in self.songs()? {
+            let source_path = kfile.source_path().to_path_buf();
+            if let Some((key, _)) = existing.insert(source_path, (kfile.id, kfile.stamp)) {
+                duplicate_keys.push(key);
+            }
+        }
 
-        let valid_kfiles_to_add: Vec<Kfile> = valid_kfiles
+        let scans: Vec<Scan> = sources
             .par_iter()
-            .filter_map(|k| {
-                if !existing_keys[..].contains(&calculate_hash(&k)) {
-                    Some(k.clone())
+            .map(|(source, library, parser)| {
+                //A song moved to another library keeps its files, but not its id
+                let unchanged = match existing.get(source.path()) {
+                    Some((key, Some(stamp))) => {
+                        source.stamp().as_ref() == Some(stamp)
+                            && *key == song_id(source.path(), library)
+                    }
+                    _ => false,
+                };
+                if unchanged {
+                    Scan::Unchanged
                 } else {
-                    None
+                    match source.kfile(library, parser) {
+                        Some(kfile) => Scan::Parsed(Box::new(kfile)),
+                        None => Scan::Invalid,
+                    }
                 }
             })
             .collect();
 
-        log::info!(
-            "Invalid songs removed: {}",
-            missing_valid_keys_to_remove.len()
-        );
-        log::info!("New songs added: {}", valid_kfiles_to_add.len());
+        let present: HashSet<&Path> = sources.iter().map(|(source, _, _)| source.path()).collect();
+        let mut summary = RefreshSummary::default();
 
-        self.write(|db| {
-            for key in missing_valid_keys_to_remove {
-                db.remove(&key);
+        let mut removed = duplicate_keys;
+        let mut inserted = Vec::new();
+        for (path, (key, _)) in existing.iter() {
+            if !present.contains(path.as_path()) {
+                removed.push(*key);
+                summary.removed += 1;
             }
-            for kfile in valid_kfiles_to_add {
-                let key = calculate_hash(&kfile);
-                db.insert(key, kfile);
+        }
+        for ((source, _, _), scan) in sources.iter().zip(scans) {
+            let existing_key = existing.get(source.path()).map(|(key, _)| *key);
+            match scan {
+                Scan::Unchanged => summary.unchanged += 1,
+                Scan::Parsed(kfile) => {
+                    if let Some(key) = existing_key {
+                        removed.push(key);
+                        summary.updated += 1;
+                    } else {
+                        summary.added += 1;
+                    }
+                    inserted.push(*kfile);
+                }
+                Scan::Invalid => {
+                    if let Some(key) = existing_key {
+                        removed.push(key);
+                        summary.removed += 1;
+                    }
+                }
             }
-        })?;
+        }
+        self.update(&removed, &inserted)?;
 
-        self.save()?;
+        log::info!("New songs added: {}", summary.added);
+        log::info!("Changed songs updated: {}", summary.updated);
+        log::info!("Invalid songs removed: {}", summary.removed);
+        log::info!("Unchanged songs: {}", summary.unchanged);
 
-        Ok(())
+        Ok(summary)
     }
 
     fn data(&self) -> Result<Self::Data, failure::Error> {
-        let mut _collection = Vec::new();
-        self.read(|db| {
-            for value in db.values() {
-                _collection.push(value.clone());
-            }
-        })?;
-
-        let collection = Collection::new(_collection);
-        log::info!("# Songs: {}", collection.by_song.len());
-        log::info!("# Artists: {}", collection.by_artist.len());
-
-        Ok(collection)
+        self.songs()
     }
 }
 
 impl Database for FavoritesDB {
     type Data = HashSet<u64>;
 
-    //If file doesn't exist, create default. Load db from file.
+    //Opens the store in the data dir, importing an old favorites.yaml into it
     fn initialize(path: &PathBuf) -> Result<Box<Self>, failure::Error> {
-        let db: FavoritesDB;
+        Ok(Box::new(FavoritesDB::open(path)?))
+    }
+
+    fn refresh(&self, _libraries: &[Library]) -> Result<RefreshSummary, failure::Error> {
+        Ok(RefreshSummary::default())
+    }
+
+    fn data(&self) -> Result<Self::Data, failure::Error> {
+        self.favorites()
+    }
+}
+
+impl Database for OverridesDB {
+    type Data = Overrides;
 
+    //If file doesn't exist, create default. Load db from file.
+    fn initialize(path: &PathBuf) -> Result<Box<Self>, failure::Error> {
         let mut db_path = path.to_path_buf();
-        db_path.push("favorites.yaml");
+        db_path.push("overrides.yaml");
 
         let exists = db_path.exists();
-        db = FavoritesDB::from_path(db_path, HashSet::new())?;
+        let db = OverridesDB::from_path(db_path, Overrides::default())?;
         if !exists {
             db.save()?;
         }
@@ -156,8 +193,8 @@ impl Database for FavoritesDB {
         Ok(Box::new(db))
     }
 
-    fn refresh(&self, _path: &PathBuf) -> Result<(), failure::Error> {
-        Ok(())
+    fn refresh(&self, _libraries: &[Library]) -> Result<RefreshSummary, failure::Error> {
+        Ok(RefreshSummary::default())
     }
 
     fn data(&self) -> Result<Self::Data, failure::Error> {
@@ -165,41 +202,292 @@ impl Database for FavoritesDB {
     }
 }
 
-pub fn add_favorite(
-    db: impl AsRef<FavoritesDB>,
+/// Number of songs affected by a refresh
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct RefreshSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+//Outcome of scanning a single song source during refresh
+enum Scan {
+    Unchanged,
+    Parsed(Box<Kfile>),
+    Invalid,
+}
+
+//A file that can produce a Kfile: an audio file with a cdg next to it, or a
+//zip archive holding both
+enum SongSource {
+    Loose(PathBuf),
+    Zip(PathBuf),
+}
+
+impl SongSource {
+    fn path(&self) -> &Path {
+        match self {
+            SongSource::Loose(path) => path,
+            SongSource::Zip(path) => path,
+        }
+    }
+
+    fn stamp(&self) -> Option<FileStamp> {
+        match self {
+            SongSource::Loose(path) => FileStamp::read(&[path, &path.with_extension("cdg")]),
+            SongSource::Zip(path) => FileStamp::read(&[path]),
+        }
+    }
+
+    fn kfile(&self, library: &Library, parser: &SongParser) -> Option<Kfile> {
+        match self {
+            SongSource::Loose(path) => Some(Kfile::new(path, library, parser)),
+            SongSource::Zip(path) => Kfile::from_zip(path, library, parser),
+        }
+    }
+
+    //Name that song_format is matched against
+    fn file_name(&self) -> Option<&str> {
+        self.path().file_stem()?.to_str()
+    }
+}
+
+//Every song source in a library: audio files with a cdg beside them, then zips
+fn library_sources(library: &Library) -> impl Iterator<Item = SongSource> {
+    let cdg_files = all_cdg(&library.path);
+    valid_cdg_audio_paths(cdg_files)
+        .into_iter()
+        .map(SongSource::Loose)
+        .chain(all_zip(&library.path).into_iter().map(SongSource::Zip))
+}
+
+//Bumped when scans pick up more details, so songs scanned before are rescanned once
+const SCAN_VERSION: u32 = 1;
+
+/// Combined size & latest modified time (ms since epoch) of the files backing a
+/// song, used to skip songs that haven't changed since the last refresh
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified: u64,
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl FileStamp {
+    fn read(paths: &[&Path]) -> Option<FileStamp> {
+        let mut stamp = FileStamp {
+            version: SCAN_VERSION,
+            ..FileStamp::default()
+        };
+        for path in paths {
+            let meta = metadata(path).ok()?;
+            let modified = meta
+                .modified()
+                .ok()?
+                .duration_since(UNIX_EPOCH)
+                .ok()?
+                .as_millis() as u64;
+            stamp.size += meta.len();
+            stamp.modified = stamp.modified.max(modified);
+        }
+        Some(stamp)
+    }
+}
+
+pub fn add_favorite(db: impl AsRef<FavoritesDB>, hash: u64) -> Result<(), failure::Error> {
+    db.as_ref().insert(hash)
+}
+
+pub fn remove_favorite(db: impl AsRef<FavoritesDB>, hash: u64) -> Result<(), failure::Error> {
+    db.as_ref().remove(hash)
+}
+
+pub fn set_song_override(
+    db: impl AsRef<OverridesDB>,
     hash: u64,
+    song_override: SongOverride,
 ) -> Result<(), rustbreak::RustbreakError> {
     db.as_ref().load()?;
 
-    db.as_ref().write(|favorites| favorites.insert(hash))?;
+    db.as_ref().write(|overrides| {
+        if song_override == SongOverride::default() {
+            overrides.songs.remove(&hash);
+        } else {
+            overrides.songs.insert(hash, song_override);
+        }
+    })?;
 
     db.as_ref().save()?;
 
     Ok(())
 }
 
-pub fn remove_favorite(
-    db: impl AsRef<FavoritesDB>,
-    hash: u64,
+pub fn set_artist_override(
+    db: impl AsRef<OverridesDB>,
+    artist: String,
+    name: Option<String>,
 ) -> Result<(), rustbreak::RustbreakError> {
     db.as_ref().load()?;
 
-    db.as_ref().write(|favorites| favorites.remove(&hash))?;
+    db.as_ref().write(|overrides| match name {
+        Some(name) if name != artist => {
+            overrides.artists.insert(artist, name);
+        }
+        _ => {
+            overrides.artists.remove(&artist);
+        }
+    })?;
 
     db.as_ref().save()?;
 
     Ok(())
 }
 
+/// Whether any song is credited to `artist`, in its files or by an override, so
+/// an artist override for it takes effect
+pub fn artist_credited(
+    collection_db: &CollectionDB,
+    overrides_db: &OverridesDB,
+    artist: &str,
+) -> Result<bool, failure::Error> {
+    if !collection_db.songs_by_artist(artist_id(artist))?.is_empty() {
+        return Ok(true);
+    }
+    overrides_db.load()?;
+    let overrides = overrides_db.data()?;
+
+    Ok(overrides
+        .songs
+        .values()
+        .any(|song| song.artist.as_deref() == Some(artist))
+        || overrides.artists.values().any(|name| name == artist))
+}
+
+/// Builds the collection from the songs in the db, with overrides applied.
+/// Overrides are reloaded first, since they're edited from the site.
+pub fn load_collection(
+    collection_db: &CollectionDB,
+    overrides_db: &OverridesDB,
+) -> Result<Collection, failure::Error> {
+    overrides_db.load()?;
+    let collection = Collection::new(
+        collection_db.data()?,
+        &overrides_db.data()?,
+        &CONFIG.artists,
+    );
+    log::info!("# Songs: {}", collection.by_song.len());
+    log::info!("# Artists: {}", collection.by_artist.len());
+
+    Ok(collection)
+}
+
 pub fn startup(no_collection_update: bool) -> Result<Collection, failure::Error> {
     let collection_db = CollectionDB::initialize(&CONFIG.data_path)?;
+    let favorites_db = FavoritesDB::initialize(&CONFIG.data_path)?;
+    let overrides_db = OverridesDB::initialize(&CONFIG.data_path)?;
+    let libraries = CONFIG.libraries();
+    migrate_ids(&collection_db, &favorites_db, &libraries)?;
     if !no_collection_update {
-        collection_db.refresh(&CONFIG.song_path)?;
+        collection_db.refresh(&libraries)?;
     }
-    collection_db.data()
+    load_collection(&collection_db, &overrides_db)
 }
 
-fn all_cdg(song_path: &PathBuf) -> Vec<PathBuf> {
+/// Re-keys songs saved under the old `DefaultHasher` ids, or under a library
+/// that's since been renamed or split off from `song_path`, to their stable
+/// `song_id`, and remaps favorites to match. Songs already keyed by their
+/// stable id are left alone, so this only does work when the ids change.
+pub fn migrate_ids(
+    collection_db: &CollectionDB,
+    favorites_db: &FavoritesDB,
+    libraries: &[Library],
+) -> Result<(), failure::Error> {
+    let mut remapped: HashMap<u64, u64> = HashMap::new();
+    let mut migrated = Vec::new();
+    for mut kfile in collection_db.songs()? {
+        let library = match kfile_library(&kfile, libraries) {
+            Some(library) => library,
+            None => continue,
+        };
+        let id = song_id(kfile.source_path(), library);
+        if kfile.id != id {
+            remapped.insert(kfile.id, id);
+            kfile.id = id;
+            kfile.library = library.label.clone();
+            kfile.artist_hash = artist_id(&kfile.artist);
+            migrated.push(kfile);
+        }
+    }
+
+    if remapped.is_empty() {
+        return Ok(());
+    }
+    let stale_keys: Vec<u64> = remapped.keys().copied().collect();
+    collection_db.update(&stale_keys, &migrated)?;
+    let favorites_remapped = favorites_db.remap(&remapped)?;
+
+    log::info!("Songs migrated to stable ids: {}", remapped.len());
+    log::info!("Favorites migrated to stable ids: {}", favorites_remapped);
+
+    Ok(())
+}
+
+/// Prints how the file names of up to `count` songs from each library are
+/// parsed by its `song_format`, without refreshing the collection
+pub fn preview_formats(libraries: &[Library], count: usize) -> Result<(), failure::Error> {
+    for library in libraries {
+        let parser = SongParser::new(&library.format)?;
+        match &library.label {
+            Some(label) => println!("{} ({:?})", label, library.path),
+            None => println!("{:?}", library.path),
+        }
+
+        let mut matched = 0;
+        let mut total = 0;
+        for source in library_sources(library).take(count) {
+            let file_name = match source.file_name() {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            total += 1;
+            println!("  {}", file_name);
+            match parser.parse(file_name) {
+                Some((idx, parse)) => {
+                    matched += 1;
+                    print!("    artist: {:?}, title: {:?}", parse.artist, parse.title);
+                    if let Some(disc) = &parse.disc {
+                        print!(", disc: {:?}", disc);
+                    }
+                    if let Some(track) = &parse.track {
+                        print!(", track: {:?}", track);
+                    }
+                    println!(" (format {})", idx + 1);
+                }
+                None => println!("    no format matched, only tags will be used"),
+            }
+        }
+        println!("{} of {} file names parsed\n", matched, total);
+    }
+
+    Ok(())
+}
+
+//Library a saved song belongs to: the one with its label, or failing that the
+//one its files are in. None if it's in none of them, refresh will remove it.
+fn kfile_library<'a>(kfile: &Kfile, libraries: &'a [Library]) -> Option<&'a Library> {
+    libraries
+        .iter()
+        .find(|library| library.label == kfile.library)
+        .or_else(|| {
+            libraries
+                .iter()
+                .find(|library| kfile.source_path().starts_with(&library.path))
+        })
+}
+
+pub fn all_cdg(song_path: &Path) -> Vec<PathBuf> {
     let mut vec = Vec::new();
     let mut glob_path = song_path.to_path_buf();
     glob_path.push("**/*.cdg");
@@ -210,55 +498,210 @@ fn all_cdg(song_path: &PathBuf) -> Vec<PathBuf> {
     vec
 }
 
-fn valid_cdg_mp3_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+pub fn all_zip(song_path: &Path) -> Vec<PathBuf> {
+    let mut vec = Vec::new();
+    let mut glob_path = song_path.to_path_buf();
+    glob_path.push("**/*.zip");
+    let glob_str = glob_path.display().to_string();
+    for file in glob(&glob_str).unwrap().filter_map(Result::ok) {
+        vec.push(file);
+    }
+    vec
+}
+
+pub fn all_audio(song_path: &Path) -> Vec<PathBuf> {
+    let mut vec = Vec::new();
+    for ext in AUDIO_EXTENSIONS.iter() {
+        let mut glob_path = song_path.to_path_buf();
+        glob_path.push(format!("**/*.{}", ext));
+        let glob_str = glob_path.display().to_string();
+        for file in glob(&glob_str).unwrap().filter_map(Result::ok) {
+            vec.push(file);
+        }
+    }
+    vec
+}
+
+//Pair each cdg with the first audio file found next to it, returns the audio paths
+fn valid_cdg_audio_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
     let mut valid = Vec::new();
-    for mut path in paths {
-        let mp3_path = path.with_extension("mp3");
-        path.set_extension("");
-        if mp3_path.exists() {
-            valid.push(path);
+    for path in paths {
+        let audio_path = AUDIO_EXTENSIONS
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .find(|audio_path| audio_path.exists());
+        if let Some(audio_path) = audio_path {
+            valid.push(audio_path);
         }
     }
     valid
 }
 
+//Searches rank a song matching on its title over one matching on its artist
+const TITLE_WEIGHT: u32 = 2;
+const ARTIST_WEIGHT: u32 = 1;
+const SONG_CODE_LEN: usize = 6;
+//Digits of a u64 in base 36, a code this long is the whole id
+const MAX_SONG_CODE_LEN: usize = 13;
+
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Collection {
     pub by_song: HashMap<u64, Kfile>,
     pub by_artist: HashMap<u64, Artist>,
+    pub song_index: SearchIndex,
+    pub artist_index: SearchIndex,
+    //Short code of each song, for the printed songbook
+    pub codes: HashMap<u64, String>,
 }
 
 impl Collection {
-    fn new(vec_kfile: Vec<Kfile>) -> Collection {
+    pub fn new(vec_kfile: Vec<Kfile>, overrides: &Overrides, artists: &ArtistConfig) -> Collection {
+        let normalizer = ArtistNormalizer::new(artists);
         let mut by_song = HashMap::new();
-        let mut by_artist = HashMap::new();
-
-        //Insert each song into by_song map; collect unique all artist names
-        let artists: HashSet<String> = vec_kfile
-            .into_iter()
-            .map(|k| {
-                by_song.insert(calculate_hash(&k), k.clone());
-                k.artist
-            })
-            .collect();
+        let mut by_artist: HashMap<u64, Artist> = HashMap::new();
+        let mut artist_names: HashMap<u64, HashMap<String, usize>> = HashMap::new();
+        let mut song_index = SearchIndex::default();
+        let mut artist_index = SearchIndex::default();
+
+        //Insert each song into by_song map and its artist's song map, creating
+        //the Artist the first time its name is seen. Songs keep the artist as
+        //credited, but are grouped by their normalized artist.
+        for mut kfile in vec_kfile {
+            overrides.apply(&mut kfile);
+            let (key, name) = normalizer.normalize(&kfile.artist);
+            kfile.artist_hash = artist_id(&key);
+            *artist_names
+                .entry(kfile.artist_hash)
+                .or_default()
+                .entry(name.clone())
+                .or_default() += 1;
+
+            let kfile_hash = kfile.id;
+            song_index.insert(kfile_hash, &kfile.song, TITLE_WEIGHT);
+            song_index.insert(kfile_hash, &kfile.artist, ARTIST_WEIGHT);
+            let artist = by_artist
+                .entry(kfile.artist_hash)
+                .or_insert_with(|| Artist::new(name));
+            artist.songs.insert(kfile_hash, kfile.clone());
+            artist.num_songs = artist.songs.len();
+            by_song.insert(kfile_hash, kfile);
+        }
 
-        //Create Artist for each artist name, with empty song map
-        for artist in artists {
-            by_artist.insert(calculate_hash(&artist), Artist::new(artist));
+        //Artists are shown under the name most of their songs use, preferring the
+        //longest (ie. with its article) then alphabetical on a tie, so it doesn't
+        //depend on scan order. Every name is searchable.
+        for (id, names) in artist_names {
+            for name in names.keys() {
+                artist_index.insert(id, name, ARTIST_WEIGHT);
+            }
+            let name = names
+                .into_iter()
+                .max_by(|(a, a_count), (b, b_count)| {
+                    a_count
+                        .cmp(b_count)
+                        .then(a.len().cmp(&b.len()))
+                        .then(b.cmp(a))
+                })
+                .map(|(name, _)| name);
+            if let (Some(artist), Some(name)) = (by_artist.get_mut(&id), name) {
+                artist.name = name;
+            }
         }
 
-        //Insert applicable songs into each artist song map
-        for (artist_hash, artist) in by_artist.iter_mut() {
-            for (kfile_hash, kfile) in by_song.iter() {
-                if kfile.artist_hash == *artist_hash {
-                    artist.songs.insert(kfile_hash.clone(), kfile.clone());
-                }
+        let codes = song_codes(by_song.keys().copied());
+        for (id, code) in codes.iter() {
+            song_index.insert(*id, code, TITLE_WEIGHT);
+        }
+
+        Collection {
+            by_song,
+            by_artist,
+            song_index,
+            artist_index,
+            codes,
+        }
+    }
+}
+
+//Normalizes credited artist names following the artists config: aliases, then
+//splitting off featured artists, then articles & case
+struct ArtistNormalizer<'a> {
+    config: &'a ArtistConfig,
+    aliases: HashMap<String, &'a str>,
+}
+
+impl<'a> ArtistNormalizer<'a> {
+    fn new(config: &'a ArtistConfig) -> ArtistNormalizer<'a> {
+        let aliases = config
+            .aliases
+            .iter()
+            .map(|(alias, name)| (alias.to_lowercase(), name.as_str()))
+            .collect();
+        ArtistNormalizer { config, aliases }
+    }
+
+    //Returns the key songs are grouped by, along with the name to show for it
+    fn normalize(&self, credited: &str) -> (String, String) {
+        //An alias for the full credit wins over splitting off featured artists
+        let credited = credited.split_whitespace().collect::<Vec<_>>().join(" ");
+        let name = match self.alias(&credited) {
+            Some(name) => name,
+            None => {
+                let primary = self.primary_artist(&credited);
+                self.alias(primary).unwrap_or(primary)
+            }
+        };
+
+        //"Beatles, The" -> "The Beatles", keyed without the article
+        let mut display = name.to_owned();
+        let mut key = name.to_owned();
+        //ASCII lowercasing keeps byte offsets the same as in name
+        let lower = name.to_ascii_lowercase();
+        for article in self.config.articles.iter() {
+            let suffix = format!(", {}", article.to_ascii_lowercase());
+            let prefix = format!("{} ", article.to_ascii_lowercase());
+            if lower.ends_with(&suffix) && name.len() > suffix.len() {
+                let base = &name[..name.len() - suffix.len()];
+                let article = &name[name.len() - suffix.len() + 2..];
+                display = format!("{} {}", article, base);
+                key = base.to_owned();
+                break;
+            }
+            if lower.starts_with(&prefix) && name.len() > prefix.len() {
+                key = name[prefix.len()..].to_owned();
+                break;
             }
-            let num_songs = artist.songs.len();
-            artist.num_songs = num_songs;
         }
 
-        Collection { by_song, by_artist }
+        if self.config.fold_case {
+            key = key.to_lowercase();
+        }
+        (key, display)
+    }
+
+    fn alias(&self, name: &str) -> Option<&'a str> {
+        self.aliases.get(&name.to_lowercase()).copied()
+    }
+
+    //First artist credited, ie. "Artist" for "Artist ft. Other" or
+    //"Artist (feat. Other)"
+    fn primary_artist<'b>(&self, name: &'b str) -> &'b str {
+        let lower = name.to_ascii_lowercase();
+        let split = self
+            .config
+            .featuring
+            .iter()
+            .flat_map(|word| {
+                let word = word.to_ascii_lowercase();
+                vec![format!(" {} ", word), format!(" ({} ", word)]
+            })
+            .filter_map(|separator| lower.find(&separator))
+            .filter(|idx| *idx > 0)
+            .min();
+        match split {
+            Some(idx) => name[..idx].trim(),
+            None => name,
+        }
     }
 }
 
@@ -280,48 +723,427 @@ impl Artist {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+/// Corrections to song metadata, kept apart from the collection db so they
+/// survive refreshes and never touch the song files
+#[derive(Default, Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Overrides {
+    #[serde(default)]
+    pub songs: HashMap<u64, SongOverride>,
+    /// Artist name -> name their songs are listed under instead, which merges
+    /// them when it's the name of another artist
+    #[serde(default)]
+    pub artists: HashMap<String, String>,
+}
+
+#[derive(Default, Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct SongOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl Overrides {
+    fn apply(&self, kfile: &mut Kfile) {
+        if let Some(song) = self.songs.get(&kfile.id) {
+            if let Some(artist) = &song.artist {
+                kfile.artist = artist.clone();
+            }
+            if let Some(title) = &song.title {
+                kfile.song = title.clone();
+            }
+        }
+
+        //Follow chains of merges, ie. A -> B -> C. Bounded so a cycle can't hang.
+        for _ in 0..self.artists.len() {
+            match self.artists.get(&kfile.artist) {
+                Some(name) => kfile.artist = name.clone(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Audio formats that can be paired with a cdg, in order of preference when
+/// more than one exists for the same song
+pub const AUDIO_EXTENSIONS: [&str; 4] = ["mp3", "ogg", "flac", "wav"];
+
+/// A playable audio & cdg pair. When `zip_path` is set, `audio_path` and
+/// `cdg_path` are the names of the members inside that archive rather than
+/// paths on disk.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Kfile {
-    pub mp3_path: PathBuf,
+    #[serde(default)]
+    pub id: u64,
+    #[serde(alias = "mp3_path")]
+    pub audio_path: PathBuf,
     pub cdg_path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zip_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
     pub artist: String,
     pub artist_hash: u64,
     pub song: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    //Length in seconds & average audio bitrate in kbps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stamp: Option<FileStamp>,
 }
 
 impl Kfile {
-    fn new(path: &PathBuf, format: &str) -> Kfile {
-        let mp3_path = PathBuf::from(path.to_str().unwrap().to_string() + ".mp3");
-        let cdg_path = PathBuf::from(path.to_str().unwrap().to_string() + ".cdg");
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-
-        let tag = Tag::read_from_path(&mp3_path).unwrap_or_default();
-        let tag_artist = tag.artist();
-        let tag_song = tag.title();
-
-        let parse_result = std::panic::catch_unwind(|| song_parse(file_name, format));
-        let (parsed_artist, parsed_song) = match parse_result {
-            Ok(Some(parse)) => (parse.artist, parse.title),
-            _ => ("<None>".to_owned(), file_name.to_owned()),
-        };
+    fn new(audio_path: &Path, library: &Library, parser: &SongParser) -> Kfile {
+        let cdg_path = audio_path.with_extension("cdg");
+        let file_name = audio_path.file_stem().unwrap().to_str().unwrap();
+
+        let tags = File::open(audio_path)
+            .map(|file| AudioTags::read(BufReader::new(file), &audio_extension(audio_path)))
+            .unwrap_or_default();
+        let (duration, bitrate) = song_length(
+            tags.duration,
+            metadata(audio_path).map(|meta| meta.len()).ok(),
+            metadata(&cdg_path).map(|meta| meta.len()).ok(),
+        );
+        let parsed = song_metadata(&tags, file_name, parser);
 
-        let artist = match tag_artist {
-            Some(s) => s,
-            None => &parsed_artist,
+        Kfile {
+            id: song_id(audio_path, library),
+            stamp: FileStamp::read(&[audio_path, &cdg_path]),
+            audio_path: audio_path.to_path_buf(),
+            cdg_path,
+            zip_path: None,
+            library: library.label.clone(),
+            artist_hash: artist_id(&parsed.artist),
+            artist: parsed.artist,
+            song: parsed.title,
+            disc: parsed.disc,
+            track: parsed.track,
+            album: tags.album,
+            genre: tags.genre,
+            year: tags.year,
+            language: tags.language,
+            duration,
+            bitrate,
+        }
+    }
+
+    //Returns None if the archive can't be read or doesn't hold an audio & cdg pair
+    fn from_zip(zip_path: &Path, library: &Library, parser: &SongParser) -> Option<Kfile> {
+        let file = File::open(zip_path).ok()?;
+        let mut archive = ZipArchive::new(BufReader::new(file)).ok()?;
+        let (audio_name, cdg_name) = zip_members(&mut archive)?;
+        let file_name = zip_path.file_stem()?.to_str()?;
+
+        let extension = audio_extension(Path::new(&audio_name));
+        let (tags, audio_size) = archive
+            .by_name(&audio_name)
+            .ok()
+            .and_then(|mut member| {
+                let size = member.size();
+                let mut data = Vec::with_capacity(size as usize);
+                member.read_to_end(&mut data).ok()?;
+                Some((AudioTags::read(Cursor::new(data), &extension), Some(size)))
+            })
+            .unwrap_or_default();
+        let cdg_size = archive.by_name(&cdg_name).map(|member| member.size()).ok();
+        let (duration, bitrate) = song_length(tags.duration, audio_size, cdg_size);
+        let parsed = song_metadata(&tags, file_name, parser);
+
+        Some(Kfile {
+            id: song_id(zip_path, library),
+            audio_path: PathBuf::from(audio_name),
+            cdg_path: PathBuf::from(cdg_name),
+            zip_path: Some(zip_path.to_path_buf()),
+            library: library.label.clone(),
+            artist_hash: artist_id(&parsed.artist),
+            artist: parsed.artist,
+            song: parsed.title,
+            disc: parsed.disc,
+            track: parsed.track,
+            album: tags.album,
+            genre: tags.genre,
+            year: tags.year,
+            language: tags.language,
+            duration,
+            bitrate,
+            stamp: FileStamp::read(&[zip_path]),
+        })
+    }
+
+    /// File on disk this song was scanned from
+    pub fn source_path(&self) -> &Path {
+        match &self.zip_path {
+            Some(zip_path) => zip_path,
+            None => &self.audio_path,
+        }
+    }
+
+    /// Whether the song's files are still where they were scanned
+    pub fn files_exist(&self) -> bool {
+        match &self.zip_path {
+            Some(zip_path) => zip_path.is_file(),
+            None => self.audio_path.is_file() && self.cdg_path.is_file(),
+        }
+    }
+
+    pub fn open_audio(&self) -> Result<SongFile, failure::Error> {
+        self.open(&self.audio_path)
+    }
+
+    pub fn open_cdg(&self) -> Result<SongFile, failure::Error> {
+        self.open(&self.cdg_path)
+    }
+
+    fn open(&self, path: &Path) -> Result<SongFile, failure::Error> {
+        match &self.zip_path {
+            Some(zip_path) => {
+                let mut archive = ZipArchive::new(BufReader::new(File::open(zip_path)?))?;
+                let mut member = archive.by_name(&path.to_string_lossy())?;
+                let mut data = Vec::with_capacity(member.size() as usize);
+                member.read_to_end(&mut data)?;
+                Ok(SongFile::Archive(Cursor::new(data)))
+            }
+            None => Ok(SongFile::Disk(BufReader::new(File::open(path)?))),
+        }
+    }
+}
+
+//Lowercase extension of a file, empty if it has none
+fn audio_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Details embedded in an audio file: id3 for mp3, vorbis comments for ogg &
+/// flac. Wav files carry no tags we read, only their length.
+#[derive(Default, Debug, PartialEq)]
+struct AudioTags {
+    artist: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    year: Option<i32>,
+    language: Option<String>,
+    disc: Option<String>,
+    track: Option<String>,
+    //Length in seconds, when the stream header has it
+    duration: Option<u32>,
+}
+
+impl AudioTags {
+    fn read<R: Read + Seek + Send + 'static>(reader: R, extension: &str) -> AudioTags {
+        match extension {
+            "mp3" => Tag::read_from(reader)
+                .map(|tag| AudioTags {
+                    artist: tag.artist().map(str::to_owned),
+                    title: tag.title().map(str::to_owned),
+                    album: tag.album().map(str::to_owned),
+                    genre: tag.genre().map(str::to_owned),
+                    year: tag
+                        .year()
+                        .or_else(|| tag.date_recorded().map(|date| date.year)),
+                    language: tag
+                        .get("TLAN")
+                        .and_then(|frame| frame.content().text())
+                        .map(str::to_owned),
+                    disc: tag.disc().map(|disc| disc.to_string()),
+                    track: tag.track().map(|track| track.to_string()),
+                    duration: tag.duration().map(|ms| ms / 1000),
+                })
+                .unwrap_or_default(),
+            "ogg" => OggStreamReader::new(reader)
+                .map(|ogg| AudioTags::from_comments(&ogg.comment_hdr.comment_list))
+                .unwrap_or_default(),
+            "flac" => FlacReader::new(reader)
+                .map(|flac| {
+                    let comments: Vec<(String, String)> = flac
+                        .tags()
+                        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                        .collect();
+                    let info = flac.streaminfo();
+                    AudioTags {
+                        duration: info
+                            .samples
+                            .filter(|_| info.sample_rate > 0)
+                            .map(|samples| (samples / u64::from(info.sample_rate)) as u32),
+                        ..AudioTags::from_comments(&comments)
+                    }
+                })
+                .unwrap_or_default(),
+            "wav" => AudioTags {
+                duration: rodio::Decoder::new(reader)
+                    .ok()
+                    .and_then(|decoder| rodio::Source::total_duration(&decoder))
+                    .map(|duration| duration.as_secs() as u32),
+                ..AudioTags::default()
+            },
+            _ => AudioTags::default(),
+        }
+    }
+
+    //Vorbis comment names are case insensitive
+    fn from_comments(comments: &[(String, String)]) -> AudioTags {
+        let find = |name: &str| {
+            comments
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim().to_owned())
+                .filter(|value| !value.is_empty())
         };
-        let song = match tag_song {
-            Some(s) => s,
-            None => &parsed_song,
+        //Numbers can be written as "3/12", dates as "1999-05-01"
+        let number =
+            |name: &str| find(name).and_then(|value| value.split('/').next().map(str::to_owned));
+        AudioTags {
+            artist: find("ARTIST"),
+            title: find("TITLE"),
+            album: find("ALBUM"),
+            genre: find("GENRE"),
+            year: find("DATE").and_then(|date| date.get(..4)?.parse().ok()),
+            language: find("LANGUAGE"),
+            disc: number("DISCNUMBER"),
+            track: number("TRACKNUMBER"),
+            duration: None,
+        }
+    }
+}
+
+//Use embedded tags if they exist, otherwise fall back to parsing the file name.
+//Disc & track parsed from the file name are kept over the tags, as they're the
+//karaoke disc code rather than an album position.
+fn song_metadata(tags: &AudioTags, file_name: &str, parser: &SongParser) -> ParseResult {
+    let mut metadata = match parser.parse(file_name) {
+        Some((_, parse)) => parse,
+        None => ParseResult {
+            title: file_name.to_owned(),
+            artist: "<None>".to_owned(),
+            disc: None,
+            track: None,
+        },
+    };
+
+    if let Some(artist) = &tags.artist {
+        metadata.artist = artist.clone();
+    }
+    if let Some(title) = &tags.title {
+        metadata.title = title.clone();
+    }
+    if metadata.disc.is_none() {
+        metadata.disc = tags.disc.clone();
+    }
+    if metadata.track.is_none() {
+        metadata.track = tags.track.clone();
+    }
+
+    metadata
+}
+
+/// A cdg stream is read in 96 byte sectors, 75 a second
+pub const CDG_BYTES_PER_SEC: u64 = 96 * 75;
+
+//Length in seconds & average bitrate in kbps of a song. The length comes from the
+//audio stream when it has one, otherwise from the size of the cdg.
+fn song_length(
+    audio_duration: Option<u32>,
+    audio_size: Option<u64>,
+    cdg_size: Option<u64>,
+) -> (Option<u32>, Option<u32>) {
+    let duration = audio_duration
+        .or_else(|| cdg_size.map(|size| (size / CDG_BYTES_PER_SEC) as u32))
+        .filter(|secs| *secs > 0);
+    let bitrate = match (duration, audio_size) {
+        (Some(secs), Some(size)) => Some((size * 8 / 1000 / u64::from(secs)) as u32),
+        _ => None,
+    };
+    (duration, bitrate)
+}
+
+/// Whether a zip archive holds an audio & cdg pair that can be played
+pub fn is_song_archive(path: &Path) -> bool {
+    File::open(path)
+        .ok()
+        .and_then(|file| ZipArchive::new(BufReader::new(file)).ok())
+        .and_then(|mut archive| zip_members(&mut archive))
+        .is_some()
+}
+
+//Find the audio & cdg pair inside an archive. Members sharing a file stem are
+//preferred, otherwise an archive holding exactly one of each is accepted.
+fn zip_members<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Option<(String, String)> {
+    let mut audio_names = Vec::new();
+    let mut cdg_names = Vec::new();
+    for idx in 0..archive.len() {
+        let name = match archive.by_index(idx) {
+            Ok(member) => member.name().to_owned(),
+            Err(_) => continue,
         };
+        let extension = audio_extension(Path::new(&name));
+        if extension == "cdg" {
+            cdg_names.push(name);
+        } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            audio_names.push(name);
+        }
+    }
 
-        let artist_hash = calculate_hash(&artist);
+    //Keep preferred formats first, so they win when several share a stem
+    audio_names.sort_by_key(|name| {
+        let extension = audio_extension(Path::new(name));
+        AUDIO_EXTENSIONS.iter().position(|ext| *ext == extension)
+    });
+
+    for cdg_name in cdg_names.iter() {
+        let cdg_stem = PathBuf::from(cdg_name).with_extension("");
+        if let Some(audio_name) = audio_names
+            .iter()
+            .find(|audio_name| PathBuf::from(audio_name).with_extension("") == cdg_stem)
+        {
+            return Some((audio_name.clone(), cdg_name.clone()));
+        }
+    }
 
-        Kfile {
-            mp3_path,
-            cdg_path,
-            artist: artist.to_string(),
-            artist_hash,
-            song: song.to_string(),
+    if audio_names.len() == 1 && cdg_names.len() == 1 {
+        return Some((audio_names.remove(0), cdg_names.remove(0)));
+    }
+    None
+}
+
+/// Song data opened for reading, either straight from disk or decompressed
+/// from a zip archive into memory
+pub enum SongFile {
+    Disk(BufReader<File>),
+    Archive(Cursor<Vec<u8>>),
+}
+
+impl Read for SongFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SongFile::Disk(reader) => reader.read(buf),
+            SongFile::Archive(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for SongFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            SongFile::Disk(reader) => reader.seek(pos),
+            SongFile::Archive(reader) => reader.seek(pos),
         }
     }
 }
@@ -329,11 +1151,23 @@ impl Kfile {
 impl Default for Kfile {
     fn default() -> Kfile {
         Kfile {
-            mp3_path: PathBuf::new(),
+            id: 0,
+            audio_path: PathBuf::new(),
             cdg_path: PathBuf::new(),
+            zip_path: None,
+            library: None,
             artist: String::from(""),
-            artist_hash: calculate_hash(&String::from("")),
+            artist_hash: artist_id(""),
             song: String::from(""),
+            disc: None,
+            track: None,
+            album: None,
+            genre: None,
+            year: None,
+            language: None,
+            duration: None,
+            bitrate: None,
+            stamp: None,
         }
     }
 }
@@ -341,6 +1175,84 @@ impl Default for Kfile {
 pub struct ParseResult {
     pub title: String,
     pub artist: String,
+    pub disc: Option<String>,
+    pub track: Option<String>,
+}
+
+/// Compiled `song_format` of a library. Each pattern is either a template like
+/// `[*] - [Artist] - [Title]`, or a regular expression with named `artist` and
+/// `title` captures, and optionally `disc` and `track`. Patterns are tried in
+/// order and the first to match wins.
+pub struct SongParser {
+    patterns: Vec<SongPattern>,
+}
+
+enum SongPattern {
+    Template(String),
+    Regex(Regex),
+}
+
+impl SongParser {
+    pub fn new(format: &SongFormat) -> Result<SongParser, failure::Error> {
+        let mut patterns = Vec::new();
+        for pattern in format.patterns() {
+            //Templates can't contain named groups, so anything with one is a regex
+            if !pattern.contains("(?P<") && !pattern.contains("(?<") {
+                patterns.push(SongPattern::Template(pattern.clone()));
+                continue;
+            }
+
+            let regex = Regex::new(pattern)
+                .map_err(|e| format_err!("Invalid song format {:?}: {}", pattern, e))?;
+            let names: Vec<&str> = regex.capture_names().flatten().collect();
+            if !names.contains(&"artist") || !names.contains(&"title") {
+                bail!(
+                    "Song format {:?} must capture both artist and title",
+                    pattern
+                );
+            }
+            patterns.push(SongPattern::Regex(regex));
+        }
+
+        Ok(SongParser { patterns })
+    }
+
+    /// Parses a file name with the first pattern that matches, returning its
+    /// index along with the result
+    pub fn parse(&self, file_name: &str) -> Option<(usize, ParseResult)> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .find_map(|(idx, pattern)| pattern.parse(file_name).map(|parse| (idx, parse)))
+    }
+}
+
+impl SongPattern {
+    fn parse(&self, file_name: &str) -> Option<ParseResult> {
+        match self {
+            SongPattern::Template(format) => {
+                std::panic::catch_unwind(|| song_parse(file_name, format))
+                    .ok()
+                    .flatten()
+            }
+            SongPattern::Regex(regex) => {
+                let captures = regex.captures(file_name)?;
+                let capture = |name| {
+                    captures
+                        .name(name)
+                        .map(|value| value.as_str().trim().to_owned())
+                        .filter(|value| !value.is_empty())
+                };
+
+                Some(ParseResult {
+                    artist: capture("artist")?,
+                    title: capture("title")?,
+                    disc: capture("disc"),
+                    track: capture("track"),
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -594,38 +1506,184 @@ fn song_parse(file_name: &str, song_format: &str) -> Option<ParseResult> {
     Some(ParseResult {
         title: title.to_owned(),
         artist: artist.to_owned(),
+        disc: None,
+        track: None,
     })
 }
 
-pub fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+/// Id of the song backed by `source_path`: the FNV-1a hash of its path relative
+/// to its library, with components joined by `/`, ie. `Artist/Song.mp3` or
+/// `Song.zip`. Songs in a named library are prefixed with its label, ie.
+/// `Drive A:Song.zip`, so the same layout on two drives doesn't collide. It only
+/// changes if the file is renamed or moved within the library, so ids survive
+/// moving the whole collection, editing tags and upgrading Rust.
+pub fn song_id(source_path: &Path, library: &Library) -> u64 {
+    let relative = source_path
+        .strip_prefix(&library.path)
+        .unwrap_or(source_path);
+    let relative = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    match &library.label {
+        Some(label) => stable_hash(format!("{}:{}", label, relative).as_bytes()),
+        None => stable_hash(relative.as_bytes()),
+    }
+}
+
+/// Id of an artist: the FNV-1a hash of their name as displayed
+pub fn artist_id(name: &str) -> u64 {
+    stable_hash(name.as_bytes())
+}
+
+/// Short codes of songs for the printed songbook: each id in base 36, cut to
+/// its last 6 digits. Songs whose codes would be the same get more digits until
+/// they aren't, so a code always stands for one song. A code is as stable as
+/// the id unless a song with the same one is added, and searching for it finds
+/// the song.
+pub fn song_codes<I: IntoIterator<Item = u64>>(ids: I) -> HashMap<u64, String> {
+    let mut codes = HashMap::new();
+    let mut clashing: Vec<u64> = ids.into_iter().collect();
+    let mut len = SONG_CODE_LEN;
+    while !clashing.is_empty() {
+        let mut by_code: HashMap<String, Vec<u64>> = HashMap::new();
+        for id in clashing.drain(..) {
+            by_code.entry(song_code(id, len)).or_default().push(id);
+        }
+        for (code, ids) in by_code {
+            if ids.len() == 1 || len == MAX_SONG_CODE_LEN {
+                codes.extend(ids.into_iter().map(|id| (id, code.clone())));
+            } else {
+                clashing.extend(ids);
+            }
+        }
+        len += 1;
+    }
+    codes
+}
+
+//The last `len` digits of the id in base 36
+fn song_code(id: u64, len: usize) -> String {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut id = id;
+    let mut code = vec![b'0'; len];
+    for digit in code.iter_mut().rev() {
+        *digit = DIGITS[(id % 36) as usize];
+        id /= 36;
+    }
+    String::from_utf8(code).unwrap()
+}
+
+//FNV-1a, its output is fixed by spec, unlike DefaultHasher which can change
+//between Rust releases
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use karaoke::config::Config;
-    use std::{fs::remove_file, path::PathBuf};
+    use karaoke::{
+        config::{ArtistConfig, Config, Library, SongFormat},
+        test_util::{libraries, song, TestDir},
+    };
+    use std::path::PathBuf;
 
     #[test]
     fn test_all_cdg() {
         let song_path = PathBuf::from("tests/test_data/songs");
         let all_cdg = all_cdg(&song_path);
         let count = all_cdg.len();
-        assert_eq!(count, 3);
+        assert_eq!(count, 4);
     }
 
     #[test]
-    fn test_valid_cdg_mp3_paths() {
+    fn test_valid_cdg_audio_paths() {
         let song_path = PathBuf::from("tests/test_data/songs");
         let all_cdg = all_cdg(&song_path);
-        let valid = valid_cdg_mp3_paths(all_cdg);
+        let valid = valid_cdg_audio_paths(all_cdg);
         let count = valid.len();
+        assert_eq!(count, 3);
+        assert!(valid.contains(&PathBuf::from(
+            "tests/test_data/songs/sub_songs/match4.flac"
+        )));
+    }
+
+    #[test]
+    fn test_audio_tags_from_comments() {
+        let comments = vec![
+            ("artist".to_owned(), "The Testers".to_owned()),
+            ("TITLE".to_owned(), "Testing 123".to_owned()),
+            ("Genre".to_owned(), "Pop".to_owned()),
+            ("DATE".to_owned(), "1999-05-01".to_owned()),
+            ("TRACKNUMBER".to_owned(), "3/12".to_owned()),
+            ("LANGUAGE".to_owned(), " ".to_owned()),
+        ];
+        let tags = AudioTags::from_comments(&comments);
+        assert_eq!(tags.artist, Some("The Testers".to_owned()));
+        assert_eq!(tags.title, Some("Testing 123".to_owned()));
+        assert_eq!(tags.genre, Some("Pop".to_owned()));
+        assert_eq!(tags.year, Some(1999));
+        assert_eq!(tags.track, Some("3".to_owned()));
+        assert_eq!(tags.language, None);
+    }
+
+    #[test]
+    fn test_song_length() {
+        //4 minutes of cdg, with a 128kbps mp3 beside it
+        let cdg_size = 240 * CDG_BYTES_PER_SEC;
+        let audio_size = 240 * 128 * 1000 / 8;
+        assert_eq!(
+            song_length(None, Some(audio_size), Some(cdg_size)),
+            (Some(240), Some(128))
+        );
+        assert_eq!(
+            song_length(Some(120), Some(audio_size), Some(cdg_size)),
+            (Some(120), Some(256))
+        );
+        assert_eq!(song_length(None, Some(audio_size), Some(0)), (None, None));
+    }
+
+    #[test]
+    fn test_all_zip() {
+        let song_path = PathBuf::from("tests/test_data/songs");
+        let all_zip = all_zip(&song_path);
+        let count = all_zip.len();
         assert_eq!(count, 2);
     }
 
+    #[test]
+    fn test_kfile_from_zip() {
+        let song_path = PathBuf::from("tests/test_data/songs");
+        let library = Library {
+            label: None,
+            path: song_path.clone(),
+            format: SongFormat::Single("[Artist] - [Title]".to_owned()),
+        };
+        let parser = SongParser::new(&library.format).unwrap();
+        let kfiles: Vec<Kfile> = all_zip(&song_path)
+            .iter()
+            .filter_map(|path| Kfile::from_zip(path, &library, &parser))
+            .collect();
+        assert_eq!(kfiles.len(), 1);
+
+        let kfile = &kfiles[0];
+        assert_eq!(kfile.audio_path, PathBuf::from("match3.mp3"));
+        assert_eq!(kfile.cdg_path, PathBuf::from("match3.cdg"));
+        assert_eq!(
+            kfile.zip_path,
+            Some(PathBuf::from("tests/test_data/songs/match3.zip"))
+        );
+        assert!(kfile.open_audio().is_ok());
+        assert!(kfile.open_cdg().is_ok());
+    }
+
     #[test]
     fn test_clean_song_parse() {
         let formats = vec![
@@ -670,37 +1728,245 @@ mod tests {
         assert!(result.unwrap().is_none());
     }
 
+    #[test]
+    fn test_song_parser() {
+        let format = SongFormat::Multiple(vec![
+            r"^(?P<disc>[A-Z]+\d+)-(?P<track>\d+) - (?P<artist>.+) - (?P<title>.+)$".to_owned(),
+            "[Title] by [Artist]".to_owned(),
+        ]);
+        let parser = SongParser::new(&format).unwrap();
+
+        let (idx, parse) = parser
+            .parse("SC8123-05 - The Testers - Testing 123")
+            .unwrap();
+        assert_eq!(idx, 0);
+        assert_eq!(parse.artist, "The Testers");
+        assert_eq!(parse.title, "Testing 123");
+        assert_eq!(parse.disc, Some("SC8123".to_owned()));
+        assert_eq!(parse.track, Some("05".to_owned()));
+
+        let (idx, parse) = parser.parse("Testing 123 by The Testers").unwrap();
+        assert_eq!(idx, 1);
+        assert_eq!(parse.artist, "The Testers");
+        assert_eq!(parse.disc, None);
+
+        assert!(parser.parse("The Testers_Testing 123").is_none());
+
+        let invalid = SongFormat::Single("(?P<artist>.+".to_owned());
+        assert!(SongParser::new(&invalid).is_err());
+        let no_title = SongFormat::Single("(?P<artist>.+)".to_owned());
+        assert!(SongParser::new(&no_title).is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let songs = vec![
+            song(1, "<None>", "SC8123 Testing 123"),
+            song(2, "The Testers", "Testing 456"),
+            song(3, "Testers, The", "Testing 789"),
+            song(4, "Testers", "Testing 000"),
+        ];
+
+        let mut overrides = Overrides::default();
+        overrides.songs.insert(
+            1,
+            SongOverride {
+                artist: Some("The Testers".to_owned()),
+                title: Some("Testing 123".to_owned()),
+            },
+        );
+        overrides
+            .artists
+            .insert("Testers, The".to_owned(), "Testers".to_owned());
+        overrides
+            .artists
+            .insert("Testers".to_owned(), "The Testers".to_owned());
+
+        //Grouping on exact names, so only the overrides merge these
+        let exact = ArtistConfig {
+            fold_case: false,
+            articles: vec![],
+            featuring: vec![],
+            aliases: HashMap::new(),
+        };
+        let collection = Collection::new(songs.clone(), &overrides, &exact);
+        assert_eq!(collection.by_song[&1].song, "Testing 123");
+        assert_eq!(collection.by_artist.len(), 1);
+        assert_eq!(collection.by_artist[&artist_id("The Testers")].num_songs, 4);
+
+        //A cycle leaves the artist on whichever name the hops end on
+        overrides
+            .artists
+            .insert("The Testers".to_owned(), "Testers, The".to_owned());
+        let collection = Collection::new(songs, &overrides, &exact);
+        assert_eq!(collection.by_song.len(), 4);
+    }
+
+    #[test]
+    fn test_artist_normalization() {
+        let kfile = |id, artist| song(id, artist, "");
+        let songs = vec![
+            kfile(1, "The Beatles"),
+            kfile(2, "Beatles, The"),
+            kfile(3, "BEATLES"),
+            kfile(4, "The  Beatles ft. Billy Preston"),
+            kfile(5, "Beatles (feat. Tony Sheridan)"),
+            kfile(6, "Simon & Garfunkel"),
+            kfile(7, "Prince and the Revolution"),
+            kfile(8, "Prince"),
+        ];
+        let mut artists = ArtistConfig::default();
+        artists
+            .aliases
+            .insert("prince and the revolution".to_owned(), "Prince".to_owned());
+
+        let collection = Collection::new(songs.clone(), &Overrides::default(), &artists);
+        assert_eq!(collection.by_artist.len(), 3);
+
+        let beatles = &collection.by_artist[&artist_id("beatles")];
+        assert_eq!(beatles.num_songs, 5);
+        assert_eq!(beatles.name, "The Beatles");
+        assert_eq!(collection.by_song[&2].artist, "Beatles, The");
+        assert_eq!(collection.by_song[&2].artist_hash, artist_id("beatles"));
+
+        let duo = &collection.by_artist[&artist_id("simon & garfunkel")];
+        assert_eq!(duo.name, "Simon & Garfunkel");
+        assert_eq!(collection.by_artist[&artist_id("prince")].num_songs, 2);
+
+        //Duos are only split when the host asks for it
+        artists.featuring.push("&".to_owned());
+        let collection = Collection::new(songs, &Overrides::default(), &artists);
+        assert_eq!(collection.by_song[&6].artist_hash, artist_id("simon"));
+    }
+
     #[test]
     fn test_kfile_new() {
-        let path = PathBuf::from("ABCD001 - The Testers - Testing 123");
-        let config = Config::default();
-        let kfile = Kfile::new(&path, &config.song_format);
+        let path = PathBuf::from("ABCD001 - The Testers - Testing 123.mp3");
+        let config = Config {
+            song_path: PathBuf::new(),
+            ..Config::default()
+        };
+        let library = &config.libraries()[0];
+        let parser = SongParser::new(&library.format).unwrap();
+        let kfile = Kfile::new(&path, library, &parser);
         let _kfile = Kfile {
-            mp3_path: PathBuf::from("ABCD001 - The Testers - Testing 123.mp3"),
+            id: song_id(&path, library),
+            audio_path: PathBuf::from("ABCD001 - The Testers - Testing 123.mp3"),
             cdg_path: PathBuf::from("ABCD001 - The Testers - Testing 123.cdg"),
+            zip_path: None,
+            library: None,
             artist: String::from("The Testers"),
-            artist_hash: calculate_hash(&String::from("The Testers")),
+            artist_hash: artist_id("The Testers"),
             song: String::from("Testing 123"),
+            disc: None,
+            track: None,
+            album: None,
+            genre: None,
+            year: None,
+            language: None,
+            duration: None,
+            bitrate: None,
+            stamp: None,
         };
         assert_eq!(kfile, _kfile);
     }
 
     #[test]
     fn test_startup() {
-        let song_path = PathBuf::from("tests/test_data/songs");
-        let data_path = PathBuf::from("tests/test_data");
-        let config = Config {
-            song_path,
-            data_path,
-            ..Config::default()
-        };
-        let initialize = CollectionDB::initialize(&config.data_path);
+        let dir = TestDir::new("startup");
+        let initialize = CollectionDB::initialize(dir.path());
         assert!(initialize.is_ok());
 
         let collection = initialize.unwrap();
-        let refresh = collection.refresh(&config.song_path);
+        let refresh = collection.refresh(&libraries());
         assert!(refresh.is_ok());
+    }
+
+    #[test]
+    fn test_stable_ids() {
+        let library = |label: Option<&str>, path: &str| Library {
+            label: label.map(str::to_owned),
+            path: PathBuf::from(path),
+            format: SongFormat::Single(String::new()),
+        };
+        let songs = library(None, "/mnt/karaoke/songs");
+        let source_path = songs.path.join("sub_songs").join("match2.mp3");
+        assert_eq!(song_id(&source_path, &songs), 2_683_107_751_710_999_660);
+        assert_eq!(
+            song_id(&source_path, &songs),
+            song_id(&PathBuf::from("sub_songs/match2.mp3"), &library(None, ""))
+        );
+        assert_ne!(
+            song_id(&source_path, &songs),
+            song_id(
+                &source_path,
+                &library(Some("Drive A"), "/mnt/karaoke/songs")
+            )
+        );
+        assert_eq!(artist_id("The Testers"), 5_417_930_624_746_565_916);
+        let codes = song_codes(vec![2_683_107_751_710_999_660, 36]);
+        assert_eq!(codes[&2_683_107_751_710_999_660], "UZHYLO");
+        assert_eq!(codes[&36], "000010");
+
+        //Ids ending in the same 6 digits get a longer code each
+        let clashing = 36u64.pow(6) + 36;
+        let codes = song_codes(vec![36, clashing, 37]);
+        assert_eq!(codes[&36], "0000010");
+        assert_eq!(codes[&clashing], "1000010");
+        assert_eq!(codes[&37], "000011");
+    }
+
+    #[test]
+    fn test_migrate_ids() {
+        let song_path = PathBuf::from("tests/test_data/songs");
+        let libraries = vec![Library {
+            label: Some("Test Songs".to_owned()),
+            path: song_path.clone(),
+            format: Config::default().song_format,
+        }];
+        let dir = TestDir::new("migrate");
+
+        let collection_db = CollectionDB::initialize(dir.path()).unwrap();
+        let favorites_db = FavoritesDB::initialize(dir.path()).unwrap();
+        let kfile = Kfile {
+            id: 1234,
+            audio_path: song_path.join("match.mp3"),
+            cdg_path: song_path.join("match.cdg"),
+            artist: String::from("The Testers"),
+            ..Kfile::default()
+        };
+        collection_db.update(&[], &[kfile]).unwrap();
+        favorites_db.insert(1234).unwrap();
+
+        migrate_ids(&collection_db, &favorites_db, &libraries).unwrap();
+
+        let new_id = song_id(&song_path.join("match.mp3"), &libraries[0]);
+        assert_eq!(collection_db.songs().unwrap().len(), 1);
+        let song = collection_db.get(new_id).unwrap().unwrap();
+        assert_eq!(song.id, new_id);
+        assert_eq!(song.library, Some("Test Songs".to_owned()));
+        assert_eq!(song.artist_hash, artist_id("The Testers"));
+        let favorites = favorites_db.data().unwrap();
+        assert!(favorites.contains(&new_id));
+        assert!(!favorites.contains(&1234));
+    }
 
-        remove_file("tests/test_data/db.yaml").unwrap();
+    #[test]
+    fn test_incremental_refresh() {
+        let dir = TestDir::new("incremental");
+        let libraries = libraries();
+        let db = CollectionDB::initialize(dir.path()).unwrap();
+        let first = db.refresh(&libraries).unwrap();
+        assert_eq!(first.added, 4);
+        assert_eq!(first.unchanged, 0);
+
+        let second = db.refresh(&libraries).unwrap();
+        assert_eq!(
+            second,
+            RefreshSummary {
+                unchanged: 4,
+                ..RefreshSummary::default()
+            }
+        );
     }
 }
@@ -4,7 +4,7 @@ use dirs::{config_dir, data_dir};
 use karaoke::embed::create_config_if_not_exists;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use std::{default::Default, fs::DirBuilder, path::PathBuf};
+use std::{collections::HashMap, default::Default, fs::DirBuilder, path::PathBuf};
 
 //Default locations, overriden if supplied in Config file or by Argument
 lazy_static! {
@@ -41,11 +41,17 @@ pub struct Config {
     pub song_path: PathBuf,
     pub data_path: PathBuf,
     pub no_collection_update: bool,
+    pub watch_collection: bool,
     pub use_web_player: bool,
     pub port: u16,
     pub port_ws: u16,
-    pub song_format: String,
+    pub song_format: SongFormat,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libraries: Vec<LibraryConfig>,
+    pub artists: ArtistConfig,
     pub player: PlayerConfig,
+    #[serde(default)]
+    pub queue: QueueConfig,
 }
 
 impl Default for Config {
@@ -54,11 +60,99 @@ impl Default for Config {
             song_path: SONG_DIR.to_path_buf(),
             data_path: DATA_DIR.to_path_buf(),
             no_collection_update: false,
+            watch_collection: true,
             use_web_player: false,
             port: 8080,
             port_ws: 9000,
-            song_format: "[*] - [Artist] - [Title]".to_owned(),
+            song_format: SongFormat::Single("[*] - [Artist] - [Title]".to_owned()),
+            libraries: vec![],
+            artists: ArtistConfig::default(),
             player: PlayerConfig::default(),
+            queue: QueueConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Song directories to scan. When no `libraries` are configured, `song_path`
+    /// and `song_format` make up a single unnamed library.
+    pub fn libraries(&self) -> Vec<Library> {
+        if self.libraries.is_empty() {
+            return vec![Library {
+                label: None,
+                path: self.song_path.clone(),
+                format: self.song_format.clone(),
+            }];
+        }
+
+        self.libraries
+            .iter()
+            .map(|library| Library {
+                label: Some(library.label.clone()),
+                path: library.path.clone(),
+                format: library
+                    .format
+                    .clone()
+                    .unwrap_or_else(|| self.song_format.clone()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibraryConfig {
+    pub label: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: Option<SongFormat>,
+}
+
+/// A song directory along with the file name format of the songs in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Library {
+    pub label: Option<String>,
+    pub path: PathBuf,
+    pub format: SongFormat,
+}
+
+/// One file name format, or a list of them to try in order until one matches
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SongFormat {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl SongFormat {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            SongFormat::Single(pattern) => std::slice::from_ref(pattern),
+            SongFormat::Multiple(patterns) => patterns,
+        }
+    }
+}
+
+/// How credited artist names are normalized to group songs by artist
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ArtistConfig {
+    pub fold_case: bool,
+    pub articles: Vec<String>,
+    pub featuring: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+}
+
+impl Default for ArtistConfig {
+    fn default() -> ArtistConfig {
+        ArtistConfig {
+            fold_case: true,
+            articles: vec!["The".to_owned(), "A".to_owned(), "An".to_owned()],
+            featuring: vec![
+                "feat.".to_owned(),
+                "ft.".to_owned(),
+                "featuring".to_owned(),
+            ],
+            aliases: HashMap::new(),
         }
     }
 }
@@ -80,6 +174,12 @@ impl Default for PlayerConfig {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct QueueConfig {
+    pub rotation: bool,
+    pub restore: bool,
+}
+
 //Use default config or override with valid values from file
 fn default_or_file(config_path: PathBuf) -> Result<Config, failure::Error> {
     let mut _config = cfg::Config::new();
@@ -146,12 +246,18 @@ pub fn load_config(
     if let Some(port) = port_ws {
         config.port_ws = port;
     }
-    log::info!("Using song dir: {:?}", config.song_path);
+    for library in config.libraries() {
+        match &library.label {
+            Some(label) => log::info!("Using song dir: {:?} ({})", library.path, label),
+            None => log::info!("Using song dir: {:?}", library.path),
+        }
+    }
     log::info!("Using data dir: {:?}", config.data_path);
     log::info!(
         "Collection to be refreshed: {:?}",
         !config.no_collection_update
     );
+    log::info!("Watch song dir for changes: {:?}", config.watch_collection);
     log::info!("Use web player: {:?}", config.use_web_player);
 
     Ok(config)
@@ -211,4 +317,63 @@ mod tests {
 
         remove_file("tests/test_data/config.yaml").unwrap();
     }
+
+    #[test]
+    fn test_libraries_config() {
+        let config_path = PathBuf::from("tests/test_data/libraries.yaml");
+        std::fs::write(
+            &config_path,
+            "libraries:\n  - label: Drive A\n    path: /mnt/a\n    format: \"[Title] - [Artist]\"\n  - label: Drive B\n    path: /mnt/b\n    format:\n      - \"(?P<artist>.+) - (?P<title>.+)\"\n      - \"[Title]_[Artist]\"\n",
+        )
+        .unwrap();
+        let config = default_or_file(config_path.clone()).unwrap();
+        remove_file(&config_path).unwrap();
+        assert_eq!(config.libraries.len(), 2);
+        assert_eq!(config.artists, ArtistConfig::default());
+        assert_eq!(
+            config.libraries[0].format,
+            Some(SongFormat::Single("[Title] - [Artist]".to_owned()))
+        );
+        assert_eq!(
+            config.libraries[1].format,
+            Some(SongFormat::Multiple(vec![
+                "(?P<artist>.+) - (?P<title>.+)".to_owned(),
+                "[Title]_[Artist]".to_owned()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_libraries() {
+        let config = Config::default();
+        assert_eq!(
+            config.libraries(),
+            vec![Library {
+                label: None,
+                path: config.song_path.clone(),
+                format: config.song_format.clone(),
+            }]
+        );
+
+        let config = Config {
+            libraries: vec![
+                LibraryConfig {
+                    label: "Drive A".to_owned(),
+                    path: PathBuf::from("/mnt/a"),
+                    format: Some(SongFormat::Single("[Title] - [Artist]".to_owned())),
+                },
+                LibraryConfig {
+                    label: "Drive B".to_owned(),
+                    path: PathBuf::from("/mnt/b"),
+                    format: None,
+                },
+            ],
+            ..Config::default()
+        };
+        let libraries = config.libraries();
+        assert_eq!(libraries.len(), 2);
+        assert_eq!(libraries[0].label, Some("Drive A".to_owned()));
+        assert_eq!(libraries[0].format.patterns(), ["[Title] - [Artist]"]);
+        assert_eq!(libraries[1].format, config.song_format);
+    }
 }
@@ -0,0 +1,314 @@
+use failure::{bail, Error};
+use karaoke::collection::Collection;
+use serde::Serialize;
+use std::{collections::HashSet, fmt::Write, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub const NAMES: [&'static str; 3] = ["csv", "json", "html"];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExportFormat, Error> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            _ => bail!("Unknown export format: {}", s),
+        }
+    }
+}
+
+/// The collection as a songbook: artists in alphabetical order, each with their
+/// songs by title
+#[derive(Serialize, Debug)]
+pub struct Songbook {
+    pub artists: Vec<SongbookArtist>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SongbookArtist {
+    pub id: u64,
+    pub name: String,
+    pub songs: Vec<SongbookSong>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SongbookSong {
+    pub id: u64,
+    pub code: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
+}
+
+/// Songbook of the songs in `favorites`, or every song, from the `library`
+/// labelled so, or every library
+pub fn songbook(
+    collection: &Collection,
+    favorites: Option<&HashSet<u64>>,
+    library: Option<&str>,
+) -> Songbook {
+    let mut artists: Vec<SongbookArtist> = collection
+        .by_artist
+        .iter()
+        .filter_map(|(id, artist)| {
+            let mut songs: Vec<SongbookSong> = artist
+                .songs
+                .values()
+                .filter(|kfile| match favorites {
+                    Some(favorites) => favorites.contains(&kfile.id),
+                    None => true,
+                })
+                .filter(|kfile| library.is_none() || kfile.library.as_deref() == library)
+                .map(|kfile| SongbookSong {
+                    id: kfile.id,
+                    code: collection.codes[&kfile.id].clone(),
+                    title: kfile.song.clone(),
+                    library: kfile.library.clone(),
+                })
+                .collect();
+            if songs.is_empty() {
+                return None;
+            }
+            songs.sort_by_cached_key(|song| (song.title.to_lowercase(), song.id));
+
+            Some(SongbookArtist {
+                id: *id,
+                name: artist.name.clone(),
+                songs,
+            })
+        })
+        .collect();
+    artists.sort_by_cached_key(|artist| (artist.name.to_lowercase(), artist.id));
+
+    Songbook { artists }
+}
+
+pub fn render(songbook: &Songbook, format: ExportFormat) -> Result<String, Error> {
+    match format {
+        ExportFormat::Csv => Ok(render_csv(songbook)),
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(songbook)?),
+        ExportFormat::Html => Ok(render_html(songbook)),
+    }
+}
+
+//One row per song, for loading into a spreadsheet
+fn render_csv(songbook: &Songbook) -> String {
+    let mut csv = String::from("code,artist,title,library\r\n");
+    for artist in songbook.artists.iter() {
+        for song in artist.songs.iter() {
+            let fields = [
+                song.code.as_str(),
+                artist.name.as_str(),
+                song.title.as_str(),
+                song.library.as_deref().unwrap_or_default(),
+            ];
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push_str("\r\n");
+        }
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+const SONGBOOK_STYLE: &str = "
+body { font-family: sans-serif; font-size: 11pt; margin: 2em; }
+h1 { text-align: center; }
+nav { columns: 4; margin-bottom: 2em; }
+nav a { display: block; color: black; text-decoration: none; }
+section { break-inside: avoid; margin-bottom: 1em; }
+h2 { font-size: 13pt; border-bottom: 1px solid black; margin-bottom: 0.25em; }
+table { border-collapse: collapse; width: 100%; }
+td { padding: 1px 4px; vertical-align: top; }
+td.code { width: 6em; font-family: monospace; font-weight: bold; }
+@media print { nav { page-break-after: always; } }
+";
+
+//A single page with no outside assets, so it prints the same anywhere. The
+//index links to each artist, songs are listed with the code to ask for.
+fn render_html(songbook: &Songbook) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Songbook</title>\n<style>{}</style>\n</head>\n<body>\n<h1>Songbook</h1>\n<nav>\n",
+        SONGBOOK_STYLE
+    );
+    for artist in songbook.artists.iter() {
+        let _ = writeln!(
+            html,
+            "<a href=\"#artist-{}\">{}</a>",
+            artist.id,
+            escape_html(&artist.name)
+        );
+    }
+    html.push_str("</nav>\n");
+
+    for artist in songbook.artists.iter() {
+        let _ = write!(
+            html,
+            "<section id=\"artist-{}\">\n<h2>{}</h2>\n<table>\n",
+            artist.id,
+            escape_html(&artist.name)
+        );
+        for song in artist.songs.iter() {
+            let _ = writeln!(
+                html,
+                "<tr><td class=\"code\">{}</td><td>{}</td></tr>",
+                song.code,
+                escape_html(&song.title)
+            );
+        }
+        html.push_str("</table>\n</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::{
+        collection::{Kfile, Overrides},
+        config::ArtistConfig,
+        test_util::song,
+    };
+
+    fn collection() -> Collection {
+        let kfile = |id, artist, title, library: Option<&str>| Kfile {
+            library: library.map(str::to_owned),
+            ..song(id, artist, title)
+        };
+        let songs = vec![
+            kfile(1, "Queen", "We Will Rock You", None),
+            kfile(2, "Queen", "Bohemian Rhapsody", Some("Drive A")),
+            kfile(3, "ABBA", "Waterloo, \"Live\"", None),
+            kfile(4, "Guns N' Roses", "<Patience>", Some("Drive A")),
+        ];
+        Collection::new(songs, &Overrides::default(), &ArtistConfig::default())
+    }
+
+    #[test]
+    fn test_songbook() {
+        let collection = collection();
+
+        let songbook = songbook(&collection, None, None);
+        let names: Vec<&str> = songbook.artists.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["ABBA", "Guns N' Roses", "Queen"]);
+        let titles: Vec<&str> = songbook.artists[2]
+            .songs
+            .iter()
+            .map(|song| song.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["Bohemian Rhapsody", "We Will Rock You"]);
+
+        let favorites: HashSet<u64> = vec![1, 3].into_iter().collect();
+        let songbook_favorites = super::songbook(&collection, Some(&favorites), None);
+        assert_eq!(songbook_favorites.artists.len(), 2);
+        let songbook_library = super::songbook(&collection, None, Some("Drive A"));
+        assert_eq!(songbook_library.artists.len(), 2);
+        assert_eq!(songbook_library.artists[1].songs[0].id, 2);
+    }
+
+    #[test]
+    fn test_render() {
+        let collection = collection();
+        let song_code = |id| collection.codes[&id].clone();
+        let songbook = songbook(&collection, None, None);
+
+        let csv = render(&songbook, ExportFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "code,artist,title,library");
+        assert_eq!(
+            lines[1],
+            format!("{},ABBA,\"Waterloo, \"\"Live\"\"\",", song_code(3))
+        );
+
+        let html = render(&songbook, ExportFormat::Html).unwrap();
+        assert!(html.contains("&lt;Patience&gt;"));
+        assert!(html.contains("Guns N&#39; Roses"));
+        assert!(html.contains(&format!("<td class=\"code\">{}</td>", song_code(1))));
+
+        let json = render(&songbook, ExportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["artists"][0]["songs"][0]["code"], song_code(3));
+    }
+
+    #[test]
+    fn test_codes_unique() {
+        //Songs whose ids end in the same 6 base 36 digits
+        let kfile = |id| song(id, "Queen", &format!("Song {}", id));
+        let clashing = 36u64.pow(6) + 1;
+        let songs = vec![kfile(1), kfile(clashing), kfile(2)];
+        let collection = Collection::new(songs, &Overrides::default(), &ArtistConfig::default());
+
+        let songbook = songbook(&collection, None, None);
+        let codes: HashSet<&str> = songbook.artists[0]
+            .songs
+            .iter()
+            .map(|song| song.code.as_str())
+            .collect();
+        assert_eq!(codes.len(), 3);
+        assert!(codes.contains("0000001"));
+        assert!(codes.contains("1000001"));
+        assert!(codes.contains("000002"));
+
+        //The printed code finds its song
+        let found: Vec<u64> = collection
+            .song_index
+            .search("1000001")
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert!(found.contains(&clashing));
+    }
+}
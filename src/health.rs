@@ -0,0 +1,376 @@
+use karaoke::{
+    collection::{
+        all_audio, all_cdg, all_zip, is_song_archive, Collection, Kfile, AUDIO_EXTENSIONS,
+        CDG_BYTES_PER_SEC,
+    },
+    config::Library,
+};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rodio::Source;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::Read,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+lazy_static! {
+    pub static ref HEALTH: Arc<Mutex<HealthCheck>> = Arc::default();
+}
+
+//A cdg stream is made of 24 byte packets, a whole number of them unless it's
+//cut short
+const CDG_PACKET_SIZE: u64 = 24;
+
+//Audio & cdg lengths further apart than this are reported as mismatched
+const DURATION_TOLERANCE_SECS: f32 = 10.0;
+
+/// Problems found in the song libraries, as a worklist for fixing them
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct HealthReport {
+    pub orphan_cdg: Vec<PathBuf>,
+    pub orphan_audio: Vec<PathBuf>,
+    pub incomplete_archives: Vec<PathBuf>,
+    pub unreadable_cdg: Vec<HealthSong>,
+    pub empty_cdg: Vec<HealthSong>,
+    pub truncated_cdg: Vec<HealthSong>,
+    pub undecodable_audio: Vec<HealthSong>,
+    pub duration_mismatches: Vec<DurationMismatch>,
+    pub duplicates: Vec<Vec<HealthSong>>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct HealthSong {
+    pub id: u64,
+    pub path: PathBuf,
+    pub artist: String,
+    pub song: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DurationMismatch {
+    pub song: HealthSong,
+    pub audio_secs: f32,
+    pub cdg_secs: f32,
+}
+
+impl From<&Kfile> for HealthSong {
+    fn from(kfile: &Kfile) -> HealthSong {
+        HealthSong {
+            id: kfile.id,
+            path: kfile.source_path().to_path_buf(),
+            artist: kfile.artist.clone(),
+            song: kfile.song.clone(),
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn num_issues(&self) -> usize {
+        self.orphan_cdg.len()
+            + self.orphan_audio.len()
+            + self.incomplete_archives.len()
+            + self.unreadable_cdg.len()
+            + self.empty_cdg.len()
+            + self.truncated_cdg.len()
+            + self.undecodable_audio.len()
+            + self.duration_mismatches.len()
+            + self.duplicates.len()
+    }
+}
+
+/// The last report made for the site, and whether a new one is being made.
+/// Checking takes too long to do while a request waits, so it's run in the
+/// background and the last report is served meanwhile.
+#[derive(Default, Debug)]
+pub struct HealthCheck {
+    report: Option<HealthReport>,
+    checking: bool,
+}
+
+impl HealthCheck {
+    /// Marks a check as started, false if one already is
+    pub fn start(&mut self) -> bool {
+        if self.checking {
+            return false;
+        }
+        self.checking = true;
+        true
+    }
+
+    pub fn finish(&mut self, report: HealthReport) {
+        self.report = Some(report);
+        self.checking = false;
+    }
+
+    pub fn checking(&self) -> bool {
+        self.checking
+    }
+
+    pub fn report(&self) -> Option<&HealthReport> {
+        self.report.as_ref()
+    }
+}
+
+//Outcome of checking the files of a single song
+#[derive(Default)]
+struct SongCheck {
+    //None if the cdg couldn't be opened or read
+    cdg_len: Option<u64>,
+    audio_secs: Option<f32>,
+}
+
+/// Checks every library for files that don't make up a song, and every song in
+/// the collection for files that can't be played. Every audio file is decoded
+/// in full, so this takes a while on a large collection.
+pub fn check(libraries: &[Library], collection: &Collection) -> HealthReport {
+    let mut report = HealthReport::default();
+
+    for library in libraries {
+        for cdg in all_cdg(&library.path) {
+            let paired = AUDIO_EXTENSIONS
+                .iter()
+                .any(|ext| cdg.with_extension(ext).exists());
+            if !paired {
+                report.orphan_cdg.push(cdg);
+            }
+        }
+        for audio in all_audio(&library.path) {
+            if !audio.with_extension("cdg").exists() {
+                report.orphan_audio.push(audio);
+            }
+        }
+        for zip in all_zip(&library.path) {
+            if !is_song_archive(&zip) {
+                report.incomplete_archives.push(zip);
+            }
+        }
+    }
+
+    let checks: Vec<(HealthSong, SongCheck)> = collection
+        .by_song
+        .par_iter()
+        .map(|(_, kfile)| (HealthSong::from(kfile), check_song(kfile)))
+        .collect();
+    for (song, check) in checks {
+        match check.cdg_len {
+            None => report.unreadable_cdg.push(song.clone()),
+            Some(0) => report.empty_cdg.push(song.clone()),
+            Some(len) if len % CDG_PACKET_SIZE != 0 => report.truncated_cdg.push(song.clone()),
+            _ => {}
+        }
+        let audio_secs = match check.audio_secs {
+            Some(secs) => secs,
+            None => {
+                report.undecodable_audio.push(song);
+                continue;
+            }
+        };
+        if let Some(len) = check.cdg_len.filter(|len| *len > 0) {
+            let cdg_secs = len as f32 / CDG_BYTES_PER_SEC as f32;
+            if (audio_secs - cdg_secs).abs() > DURATION_TOLERANCE_SECS {
+                report.duration_mismatches.push(DurationMismatch {
+                    song,
+                    audio_secs,
+                    cdg_secs,
+                });
+            }
+        }
+    }
+
+    report.duplicates = duplicates(collection);
+    sort_report(&mut report);
+
+    report
+}
+
+fn check_song(kfile: &Kfile) -> SongCheck {
+    let cdg_len = kfile.open_cdg().ok().and_then(|mut cdg| {
+        let mut data = Vec::new();
+        cdg.read_to_end(&mut data).ok()?;
+        Some(data.len() as u64)
+    });
+
+    //Decoders can panic on malformed files, that just means it's undecodable
+    let audio_secs = kfile.open_audio().ok().and_then(|audio| {
+        catch_unwind(AssertUnwindSafe(|| {
+            let decoder = rodio::Decoder::new(audio).ok()?;
+            let samples_per_sec = u32::from(decoder.channels()) * decoder.sample_rate();
+            let samples = decoder.count();
+            if samples == 0 || samples_per_sec == 0 {
+                return None;
+            }
+            Some(samples as f32 / samples_per_sec as f32)
+        }))
+        .ok()
+        .flatten()
+    });
+
+    SongCheck {
+        cdg_len,
+        audio_secs,
+    }
+}
+
+//Songs sharing an artist & title, once both are normalized. Artists already are
+//by the collection, so their ids are compared.
+fn duplicates(collection: &Collection) -> Vec<Vec<HealthSong>> {
+    let mut groups: HashMap<(u64, String), Vec<HealthSong>> = HashMap::new();
+    for kfile in collection.by_song.values() {
+        groups
+            .entry((kfile.artist_hash, normalize_title(&kfile.song)))
+            .or_default()
+            .push(HealthSong::from(kfile));
+    }
+
+    groups
+        .into_values()
+        .filter(|songs| songs.len() > 1)
+        .collect()
+}
+
+//Lowercase words, ignoring punctuation, ie. "Don't Stop" -> "dont stop"
+fn normalize_title(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+//Keep the report in a stable order, so it reads as a worklist
+fn sort_report(report: &mut HealthReport) {
+    report.orphan_cdg.sort();
+    report.orphan_audio.sort();
+    report.incomplete_archives.sort();
+    report.unreadable_cdg.sort_by(|a, b| a.path.cmp(&b.path));
+    report.empty_cdg.sort_by(|a, b| a.path.cmp(&b.path));
+    report.truncated_cdg.sort_by(|a, b| a.path.cmp(&b.path));
+    report.undecodable_audio.sort_by(|a, b| a.path.cmp(&b.path));
+    report
+        .duration_mismatches
+        .sort_by(|a, b| a.song.path.cmp(&b.song.path));
+    for songs in report.duplicates.iter_mut() {
+        songs.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    report.duplicates.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+}
+
+/// Prints the report for the --health-report flag
+pub fn print_report(report: &HealthReport) {
+    print_paths("CDG files without audio", &report.orphan_cdg);
+    print_paths("Audio files without a CDG", &report.orphan_audio);
+    print_paths(
+        "Archives without an audio & CDG pair",
+        &report.incomplete_archives,
+    );
+    print_songs("CDG that can't be read", &report.unreadable_cdg);
+    print_songs("Empty CDG", &report.empty_cdg);
+    print_songs("Truncated CDG", &report.truncated_cdg);
+    print_songs("Audio that can't be decoded", &report.undecodable_audio);
+
+    println!(
+        "Audio & CDG lengths don't match ({}):",
+        report.duration_mismatches.len()
+    );
+    for mismatch in report.duration_mismatches.iter() {
+        println!(
+            "  {:?} audio {:.1}s, cdg {:.1}s",
+            mismatch.song.path, mismatch.audio_secs, mismatch.cdg_secs
+        );
+    }
+    println!();
+
+    println!("Likely duplicates ({}):", report.duplicates.len());
+    for songs in report.duplicates.iter() {
+        println!("  {} - {}", songs[0].artist, songs[0].song);
+        for song in songs {
+            println!("    {:?}", song.path);
+        }
+    }
+    println!();
+
+    println!("{} issues found", report.num_issues());
+}
+
+fn print_paths(title: &str, paths: &[PathBuf]) {
+    println!("{} ({}):", title, paths.len());
+    for path in paths {
+        println!("  {:?}", path);
+    }
+    println!();
+}
+
+fn print_songs(title: &str, songs: &[HealthSong]) {
+    println!("{} ({}):", title, songs.len());
+    for song in songs {
+        println!("  {:?} ({} - {})", song.path, song.artist, song.song);
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::{
+        collection::{Database, Overrides},
+        config::ArtistConfig,
+        test_util::{libraries, scanned_db, TestDir},
+    };
+
+    #[test]
+    fn test_check() {
+        let libraries = libraries();
+        let mut songs = scanned_db(&TestDir::new("health")).data().unwrap();
+
+        //A second copy of a song makes a duplicate
+        let mut copy = songs[0].clone();
+        copy.id += 1;
+        songs.push(copy);
+        //A song whose cdg has gone can't be read, which isn't the same as empty
+        let mut gone = songs[0].clone();
+        gone.id += 2;
+        gone.song = "Gone".to_owned();
+        gone.cdg_path = PathBuf::from("tests/test_data/songs/gone.cdg");
+        songs.push(gone);
+        let collection = Collection::new(songs, &Overrides::default(), &ArtistConfig::default());
+
+        let report = check(&libraries, &collection);
+        assert_eq!(
+            report.orphan_cdg,
+            vec![PathBuf::from("tests/test_data/songs/nomatch.cdg")]
+        );
+        assert!(report.orphan_audio.is_empty());
+        assert_eq!(
+            report.incomplete_archives,
+            vec![PathBuf::from(
+                "tests/test_data/songs/sub_songs/nomatch2.zip"
+            )]
+        );
+        //The test songs are placeholders, with no audio to decode
+        assert_eq!(report.undecodable_audio.len(), 6);
+        assert_eq!(report.unreadable_cdg.len(), 1);
+        assert_eq!(report.unreadable_cdg[0].song, "Gone");
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].len(), 2);
+
+        //Only one check runs at a time, its report is kept when it's done
+        let mut check = HealthCheck::default();
+        assert!(check.start());
+        assert!(!check.start());
+        check.finish(report);
+        assert!(!check.checking());
+        assert_eq!(check.report().unwrap().duplicates.len(), 1);
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(normalize_title("Don't  Stop Me Now!"), "dont stop me now");
+    }
+}
@@ -0,0 +1,317 @@
+use failure::Error;
+use karaoke::{log_error, queue::QueueEntry, store::HistoryDB, CONFIG};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+lazy_static! {
+    pub static ref HISTORY: Arc<Mutex<History>> = Arc::from(Mutex::from(open_history()));
+}
+
+fn open_history() -> History {
+    match HistoryDB::open(&CONFIG.data_path) {
+        Ok(db) => History {
+            db: Some(db),
+            current: None,
+        },
+        Err(e) => {
+            log_error(&e);
+            History::default()
+        }
+    }
+}
+
+//A break this long between songs ends the night
+const SESSION_GAP: u64 = 4 * 60 * 60 * 1000;
+
+//A song skipped before this much of it was heard isn't counted as played
+const COUNTED_COMPLETION: f32 = 50.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayOutcome {
+    #[serde(rename = "playing")]
+    Playing,
+    #[serde(rename = "finished")]
+    Finished,
+    #[serde(rename = "skipped")]
+    Skipped,
+}
+
+/// A song played, times are milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Play {
+    pub song_id: u64,
+    pub artist_id: u64,
+    pub title: String,
+    pub artist: String,
+    pub singer: Option<String>,
+    pub duration: Option<u32>,
+    pub started: u64,
+    pub ended: Option<u64>,
+    pub outcome: PlayOutcome,
+    //Percent of the song heard, when its length is known
+    pub completion: Option<f32>,
+}
+
+impl Play {
+    fn counted(&self) -> bool {
+        match (self.outcome, self.completion) {
+            (PlayOutcome::Finished, _) => true,
+            (PlayOutcome::Skipped, Some(completion)) => completion >= COUNTED_COMPLETION,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CurrentPlay {
+    entry_id: u64,
+    play: Play,
+}
+
+/// Log of every song played, written to the data dir as each one starts and
+/// again when it finishes or is skipped
+#[derive(Debug, Default)]
+pub struct History {
+    db: Option<HistoryDB>,
+    current: Option<CurrentPlay>,
+}
+
+impl History {
+    /// Records `entry` as started. Whatever was playing is recorded as skipped,
+    /// unless it's the same queue entry, which the web player asks for again if
+    /// it has to retry.
+    pub fn start(&mut self, entry: &QueueEntry) {
+        self.start_at(entry, now());
+    }
+
+    /// Records the song playing as finished or skipped. `played` is how far
+    /// into the song it got in milliseconds, when the player knows, otherwise
+    /// it's the time since it started.
+    pub fn finish(&mut self, outcome: PlayOutcome, played: Option<u64>) {
+        self.finish_at(outcome, played, now());
+    }
+
+    fn start_at(&mut self, entry: &QueueEntry, started: u64) {
+        if let Some(current) = &self.current {
+            if entry.id != 0 && current.entry_id == entry.id {
+                return;
+            }
+        }
+        self.finish_at(PlayOutcome::Skipped, None, started);
+
+        let kfile = &entry.kfile;
+        let play = Play {
+            song_id: kfile.id,
+            artist_id: kfile.artist_hash,
+            title: kfile.song.clone(),
+            artist: kfile.artist.clone(),
+            singer: entry.singer.clone(),
+            duration: kfile.duration,
+            started,
+            ended: None,
+            outcome: PlayOutcome::Playing,
+            completion: None,
+        };
+        self.write(&play);
+        self.current = Some(CurrentPlay {
+            entry_id: entry.id,
+            play,
+        });
+    }
+
+    fn finish_at(&mut self, outcome: PlayOutcome, played: Option<u64>, ended: u64) {
+        let mut play = match self.current.take() {
+            Some(current) => current.play,
+            None => return,
+        };
+        let played = played.unwrap_or_else(|| ended.saturating_sub(play.started));
+
+        play.ended = Some(ended);
+        play.outcome = outcome;
+        play.completion = match outcome {
+            PlayOutcome::Finished => Some(100.0),
+            _ => match play.duration {
+                Some(secs) if secs > 0 => Some((played as f32 / (secs as f32 * 10.0)).min(100.0)),
+                _ => None,
+            },
+        };
+        self.write(&play);
+    }
+
+    fn write(&self, play: &Play) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.insert(play.started, play) {
+                log_error(&e);
+            }
+        }
+    }
+
+    /// Plays started from `from` up to `to`, oldest first
+    pub fn plays(&self, from: u64, to: u64) -> Result<Vec<Play>, Error> {
+        match &self.db {
+            Some(db) => db.range(from, to),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PlayCount {
+    pub id: u64,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_name: Option<String>,
+    pub plays: usize,
+}
+
+/// Songs played the most, most first
+pub fn top_songs(plays: &[Play], limit: usize) -> Vec<PlayCount> {
+    top(plays, limit, |play| {
+        (play.song_id, play.title.clone(), Some(play.artist.clone()))
+    })
+}
+
+/// Artists played the most, most first
+pub fn top_artists(plays: &[Play], limit: usize) -> Vec<PlayCount> {
+    top(plays, limit, |play| {
+        (play.artist_id, play.artist.clone(), None)
+    })
+}
+
+fn top<F>(plays: &[Play], limit: usize, key: F) -> Vec<PlayCount>
+where
+    F: Fn(&Play) -> (u64, String, Option<String>),
+{
+    let mut counts: HashMap<u64, PlayCount> = HashMap::new();
+    for play in plays.iter().filter(|play| play.counted()) {
+        let (id, name, artist_name) = key(play);
+        counts
+            .entry(id)
+            .or_insert(PlayCount {
+                id,
+                name,
+                artist_name,
+                plays: 0,
+            })
+            .plays += 1;
+    }
+
+    let mut counts: Vec<PlayCount> = counts.into_values().collect();
+    counts.sort_by_cached_key(|count| (std::cmp::Reverse(count.plays), count.name.to_lowercase()));
+    counts.truncate(limit);
+    counts
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Session {
+    pub started: u64,
+    pub ended: u64,
+    pub songs: usize,
+    pub singers: usize,
+}
+
+/// Plays, oldest first, split into nights, newest first
+pub fn sessions(plays: &[Play]) -> Vec<Session> {
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut singers = HashSet::new();
+
+    for play in plays {
+        let ended = play.ended.unwrap_or(play.started);
+        match sessions.last_mut() {
+            Some(session) if play.started < session.ended + SESSION_GAP => {
+                session.ended = session.ended.max(ended);
+                session.songs += 1;
+            }
+            _ => {
+                singers.clear();
+                sessions.push(Session {
+                    started: play.started,
+                    ended,
+                    songs: 1,
+                    singers: 0,
+                });
+            }
+        }
+        if let (Some(session), Some(singer)) = (sessions.last_mut(), &play.singer) {
+            if singers.insert(singer.to_lowercase()) {
+                session.singers += 1;
+            }
+        }
+    }
+
+    sessions.reverse();
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::test_util::{queued, song, TestDir};
+
+    const MINUTE: u64 = 60 * 1000;
+
+    fn entry(id: u64, title: &str, artist: &str, singer: &str) -> QueueEntry {
+        queued(song(id, artist, title), Some(singer))
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = TestDir::new("history_db");
+        let mut history = History {
+            db: Some(HistoryDB::open(dir.path()).unwrap()),
+            current: None,
+        };
+
+        let night = 1_000_000 * MINUTE;
+        history.start_at(&entry(1, "Waterloo", "ABBA", "Ann"), night);
+        history.finish_at(PlayOutcome::Finished, None, night + 3 * MINUTE);
+        //Asked for twice by the web player, then skipped 20 seconds in
+        let queen = entry(2, "Bohemian Rhapsody", "Queen", "Bob");
+        history.start_at(&queen, night + 4 * MINUTE);
+        history.start_at(&queen, night + 4 * MINUTE + 500);
+        history.finish_at(PlayOutcome::Skipped, Some(20_000), night + 5 * MINUTE);
+        //Replaced by the next song after 3 minutes
+        history.start_at(
+            &entry(3, "Under Pressure", "Queen", "ann"),
+            night + 6 * MINUTE,
+        );
+        history.start_at(&entry(1, "Waterloo", "ABBA", "Cat"), night + 9 * MINUTE);
+        history.finish_at(PlayOutcome::Finished, None, night + 12 * MINUTE);
+        //The next night
+        let next_night = night + 24 * 60 * MINUTE;
+        history.start_at(&entry(3, "Under Pressure", "Queen", "Dan"), next_night);
+
+        let plays = history.plays(0, u64::MAX).unwrap();
+        assert_eq!(plays.len(), 5);
+        assert_eq!(plays[1].completion, Some(10.0));
+        assert_eq!(plays[2].outcome, PlayOutcome::Skipped);
+        assert_eq!(plays[2].completion, Some(90.0));
+        assert_eq!(plays[4].outcome, PlayOutcome::Playing);
+        assert_eq!(history.plays(night + MINUTE, next_night).unwrap().len(), 3);
+
+        let songs = top_songs(&plays, 10);
+        let songs: Vec<(u64, usize)> = songs.iter().map(|song| (song.id, song.plays)).collect();
+        assert_eq!(songs, vec![(1, 2), (3, 1)]);
+        let artists = top_artists(&plays, 1);
+        assert_eq!(artists[0].name, "ABBA");
+
+        let sessions = sessions(&plays);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].started, night);
+        assert_eq!(sessions[1].ended, night + 12 * MINUTE);
+        assert_eq!((sessions[1].songs, sessions[1].singers), (4, 3));
+        assert_eq!(sessions[0].songs, 1);
+    }
+}
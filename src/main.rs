@@ -1,11 +1,13 @@
 extern crate self as karaoke;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use env_logger::Env;
 use failure::{bail, format_err, Error, ResultExt};
 use karaoke::{
-    collection::COLLECTION,
+    collection::{preview_formats, COLLECTION},
     config::{load_config, Config},
+    export::{render, songbook, ExportFormat},
+    store::FavoritesDB,
 };
 use lazy_static::lazy_static;
 use std::{fs::metadata, path::PathBuf};
@@ -14,13 +16,28 @@ mod channel;
 mod collection;
 mod config;
 mod embed;
+mod export;
+mod health;
+mod history;
+mod pitch;
+mod playback;
 mod player;
 mod queue;
+mod search;
 mod site;
+mod store;
+#[cfg(test)]
+mod test_util;
+mod thumbnail;
+mod watcher;
 mod websocket;
 mod worker;
 
+//Number of songs per library shown by --preview-format
+const PREVIEW_SIZE: usize = 25;
+
 lazy_static! {
+    static ref ARGS: ArgMatches<'static> = args();
     pub static ref CONFIG: Config = {
         let config = get_config();
         match config {
@@ -44,8 +61,22 @@ fn run() -> Result<(), Error> {
     env_logger::from_env(Env::default().default_filter_or("karaoke_rs=info")).init();
 
     lazy_static::initialize(&CONFIG);
+    if ARGS.is_present("preview-format") {
+        return preview_formats(&CONFIG.libraries(), PREVIEW_SIZE);
+    }
     lazy_static::initialize(&COLLECTION);
+    if ARGS.is_present("health-report") {
+        let report = karaoke::health::check(&CONFIG.libraries(), &COLLECTION.read().unwrap());
+        karaoke::health::print_report(&report);
+        return Ok(());
+    }
+    if let Some(format) = ARGS.value_of("export") {
+        return export_songbook(format.parse()?);
+    }
     karaoke::embed::unload_files();
+    if CONFIG.watch_collection {
+        karaoke::watcher::run();
+    }
     if !&CONFIG.use_web_player {
         karaoke::player::run();
     }
@@ -54,8 +85,8 @@ fn run() -> Result<(), Error> {
     Ok(())
 }
 
-fn get_config() -> Result<Config, failure::Error> {
-    let matches = App::new("karoake-rs")
+fn args() -> ArgMatches<'static> {
+    App::new("karoake-rs")
         .version(env!("CARGO_PKG_VERSION"))
         .author("tarkah <admin@tarkah.dev>")
         .about("A simple, network enabled karaoke player in Rust")
@@ -113,7 +144,72 @@ fn get_config() -> Result<Config, failure::Error> {
                 .help("Specify a websocket port when using the web player feature")
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("health-report")
+                .long("health-report")
+                .help("Check the collection for broken, orphaned and duplicate songs, then exit"),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .value_name("FORMAT")
+                .help("Export the collection as a songbook, then exit")
+                .takes_value(true)
+                .possible_values(&ExportFormat::NAMES),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help("Write the exported songbook to FILE instead of stdout")
+                .takes_value(true)
+                .requires("export"),
+        )
+        .arg(
+            Arg::with_name("favorites-only")
+                .long("favorites-only")
+                .help("Only export favorite songs")
+                .requires("export"),
+        )
+        .arg(
+            Arg::with_name("library")
+                .long("library")
+                .value_name("LABEL")
+                .help("Only export songs from the library with this label")
+                .takes_value(true)
+                .requires("export"),
+        )
+        .arg(
+            Arg::with_name("preview-format")
+                .long("preview-format")
+                .help("Show how song file names are parsed by song_format, then exit"),
+        )
+        .get_matches()
+}
+
+fn export_songbook(format: ExportFormat) -> Result<(), Error> {
+    let favorites = if ARGS.is_present("favorites-only") {
+        Some(FavoritesDB::open(&CONFIG.data_path)?.favorites()?)
+    } else {
+        None
+    };
+    let songbook = songbook(
+        &COLLECTION.read().unwrap(),
+        favorites.as_ref(),
+        ARGS.value_of("library"),
+    );
+    let output = render(&songbook, format)?;
+
+    match ARGS.value_of("output") {
+        Some(path) => std::fs::write(path, output)?,
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+fn get_config() -> Result<Config, failure::Error> {
+    let matches = &*ARGS;
 
     let config_path: Option<PathBuf>;
     let song_path: Option<PathBuf>;
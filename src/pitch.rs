@@ -0,0 +1,387 @@
+use rodio::Source;
+use std::{
+    sync::{
+        atomic::{AtomicI8, AtomicU8, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Furthest a song can be shifted, in semitones either way
+pub const MAX_KEY: i8 = 6;
+
+/// Slowest and fastest a song can be played, in percent of its normal tempo
+pub const MIN_TEMPO: u8 = 80;
+pub const MAX_TEMPO: u8 = 120;
+
+/// Key and tempo of the song playing, shared with its source so they can be
+/// changed as it plays
+#[derive(Debug)]
+pub struct Controls {
+    key: AtomicI8,
+    tempo: AtomicU8,
+}
+
+impl Controls {
+    pub fn new(key: i8, tempo: u8) -> Controls {
+        Controls {
+            key: AtomicI8::new(key),
+            tempo: AtomicU8::new(tempo),
+        }
+    }
+
+    pub fn set_key(&self, key: i8) {
+        self.key.store(key, Relaxed);
+    }
+
+    pub fn set_tempo(&self, tempo: u8) {
+        self.tempo.store(tempo, Relaxed);
+    }
+
+    /// Percent of the normal tempo
+    pub fn tempo(&self) -> u8 {
+        self.tempo.load(Relaxed).clamp(MIN_TEMPO, MAX_TEMPO)
+    }
+
+    fn speed(&self) -> f32 {
+        f32::from(self.tempo()) / 100.0
+    }
+
+    //Speeding the song up raises its pitch as much, which is taken back off
+    fn pitch(&self) -> f32 {
+        ratio(self.key.load(Relaxed)) / self.speed()
+    }
+}
+
+/// Stretches `input` to be played at the tempo and key of `controls`
+pub fn stretch<S>(input: S, controls: Arc<Controls>) -> PitchShift<Tempo<S>>
+where
+    S: Source<Item = i16>,
+{
+    PitchShift::new(Tempo::new(input, controls.clone()), controls)
+}
+
+/// Plays `input` faster or slower by resampling it, which changes its pitch
+/// along with its tempo
+pub struct Tempo<S> {
+    input: S,
+    controls: Arc<Controls>,
+    channels: usize,
+    channel: usize,
+    //Output is read between these two input frames, `position` of the way
+    //from the first to the second. They're refilled in place as it plays.
+    frames: [Vec<f32>; 2],
+    position: f32,
+    //Input has run out, the second frame holds the last one
+    ended: bool,
+}
+
+impl<S> Tempo<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(input: S, controls: Arc<Controls>) -> Tempo<S> {
+        let channels = usize::from(input.channels().max(1));
+        let mut tempo = Tempo {
+            input,
+            controls,
+            channels,
+            channel: 0,
+            frames: [vec![0.0; channels], vec![0.0; channels]],
+            position: 0.0,
+            ended: false,
+        };
+        if !read_frame(&mut tempo.input, &mut tempo.frames[0]) {
+            //Nothing to play, the first frame is never reached
+            tempo.channel = channels;
+            tempo.position = 1.0;
+            tempo.ended = true;
+        } else if !read_frame(&mut tempo.input, &mut tempo.frames[1]) {
+            tempo.hold_last();
+        }
+        tempo
+    }
+
+    //Plays the last frame out before ending
+    fn hold_last(&mut self) {
+        let (first, second) = self.frames.split_at_mut(1);
+        second[0].copy_from_slice(&first[0]);
+        self.ended = true;
+    }
+}
+
+//Fills `frame` with the next frame of `input`, false if there isn't a whole one
+fn read_frame<S>(input: &mut S, frame: &mut [f32]) -> bool
+where
+    S: Iterator<Item = i16>,
+{
+    for sample in frame.iter_mut() {
+        match input.next() {
+            Some(next) => *sample = f32::from(next),
+            None => return false,
+        }
+    }
+    true
+}
+
+impl<S> Iterator for Tempo<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        //Moves on to the next output frame
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.position += self.controls.speed();
+            while self.position >= 1.0 {
+                if self.ended {
+                    return None;
+                }
+                self.position -= 1.0;
+                self.frames.swap(0, 1);
+                if !read_frame(&mut self.input, &mut self.frames[1]) {
+                    self.hold_last();
+                }
+            }
+        }
+
+        let channel = self.channel;
+        self.channel += 1;
+        let a = self.frames[0][channel];
+        let b = self.frames[1][channel];
+        Some((a + (b - a) * self.position) as i16)
+    }
+}
+
+impl<S> Source for Tempo<S>
+where
+    S: Source<Item = i16>,
+{
+    //Frames are only known as they're read
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    //Depends on the tempo, which can change
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+//Length of the delay line the shifted audio is read back from. Longer smears
+//the start of notes, shorter sounds rougher.
+const WINDOW_MS: u32 = 40;
+
+/// Shifts the pitch of `input` to the key of `controls` without changing its
+/// tempo, so it stays in time with the cdg. The key can be changed while it
+/// plays.
+///
+/// The audio is written to a short delay line and read back from two points
+/// moving through it at the new speed, half a window apart. Each fades out as
+/// it wraps around the window while the other is loudest, hiding the jump.
+pub struct PitchShift<S> {
+    input: S,
+    controls: Arc<Controls>,
+    ratio: f32,
+    channels: usize,
+    channel: usize,
+    window: f32,
+    buffers: Vec<Vec<f32>>,
+    write: usize,
+    //Samples the first read point is behind the write point
+    delay: f32,
+}
+
+impl<S> PitchShift<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(input: S, controls: Arc<Controls>) -> PitchShift<S> {
+        let channels = usize::from(input.channels().max(1));
+        let window = (input.sample_rate() * WINDOW_MS / 1000).max(2) as usize;
+        PitchShift {
+            input,
+            controls,
+            ratio: 1.0,
+            channels,
+            channel: 0,
+            window: window as f32,
+            //Room to interpolate past the end of the window
+            buffers: vec![vec![0.0; window + 2]; channels],
+            write: 0,
+            delay: 0.0,
+        }
+    }
+
+    fn read(&self, buffer: &[f32], delay: f32) -> f32 {
+        let len = buffer.len() as f32;
+        let pos = self.write as f32 - delay + len;
+        let index = pos.floor();
+        let fraction = pos - index;
+        let a = buffer[index as usize % buffer.len()];
+        let b = buffer[(index as usize + 1) % buffer.len()];
+        a + (b - a) * fraction
+    }
+
+    //Loudest in the middle of the window, silent at the ends
+    fn gain(&self, delay: f32) -> f32 {
+        1.0 - (2.0 * delay / self.window - 1.0).abs()
+    }
+}
+
+fn ratio(key: i8) -> f32 {
+    2f32.powf(f32::from(key.clamp(-MAX_KEY, MAX_KEY)) / 12.0)
+}
+
+impl<S> Iterator for PitchShift<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.input.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.channels;
+
+        //Every channel of a frame is read from the same point
+        if channel == 0 {
+            self.ratio = self.controls.pitch();
+            self.write = (self.write + 1) % self.buffers[0].len();
+            self.delay += 1.0 - self.ratio;
+            if self.delay < 0.0 {
+                self.delay += self.window;
+            } else if self.delay >= self.window {
+                self.delay -= self.window;
+            }
+        }
+        self.buffers[channel][self.write] = f32::from(sample);
+
+        //Still written to the delay line, so the key can change mid song
+        if (self.ratio - 1.0).abs() < f32::EPSILON {
+            return Some(sample);
+        }
+
+        let buffer = &self.buffers[channel];
+        let other = (self.delay + self.window / 2.0) % self.window;
+        let shifted = self.read(buffer, self.delay) * self.gain(self.delay)
+            + self.read(buffer, other) * self.gain(other);
+        Some(shifted.max(f32::from(i16::MIN)).min(f32::from(i16::MAX)) as i16)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for PitchShift<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 44100;
+
+    fn sine(freq: f32) -> Vec<i16> {
+        (0..RATE)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                ((2.0 * std::f32::consts::PI * freq * t).sin() * 10_000.0) as i16
+            })
+            .collect()
+    }
+
+    fn shift(samples: &[i16], key: i8, tempo: u8) -> Vec<i16> {
+        let input = SamplesBuffer::new(1, RATE, samples.to_vec());
+        stretch(input, Arc::new(Controls::new(key, tempo))).collect()
+    }
+
+    //Frequency of a tone, the loudest in the first quarter second. The two read
+    //points can cancel each other out for a moment, which throws off counting
+    //zero crossings.
+    fn frequency(samples: &[i16]) -> f32 {
+        let samples = &samples[..RATE as usize / 4];
+        let power = |freq: f32| {
+            let coeff = 2.0 * (2.0 * std::f32::consts::PI * freq / RATE as f32).cos();
+            let (mut a, mut b) = (0.0, 0.0);
+            for &sample in samples {
+                let next = f32::from(sample) + coeff * a - b;
+                b = a;
+                a = next;
+            }
+            a * a + b * b - coeff * a * b
+        };
+        (100..1000)
+            .map(|freq| freq as f32)
+            .max_by(|a, b| power(*a).partial_cmp(&power(*b)).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pitch_shift() {
+        let tone = sine(440.0);
+        assert_eq!(shift(&tone, 0, 100), tone);
+
+        let up = shift(&tone, 6, 100);
+        assert_eq!(up.len(), tone.len());
+        assert!((frequency(&up) - 440.0 * ratio(6)).abs() < 10.0);
+
+        let down = shift(&tone, -6, 100);
+        assert!((frequency(&down) - 440.0 * ratio(-6)).abs() < 10.0);
+
+        //Out of range keys go as far as they can
+        assert_eq!(ratio(12), ratio(MAX_KEY));
+    }
+
+    #[test]
+    fn test_tempo() {
+        let tone = sine(440.0);
+
+        //Faster is shorter, at the same pitch
+        let fast = shift(&tone, 0, 120);
+        assert!((fast.len() as f32 - RATE as f32 / 1.2).abs() < 10.0);
+        assert!((frequency(&fast) - 440.0).abs() < 10.0);
+
+        //Nothing is lost off the end
+        assert_eq!(shift(&[1, 2, 3], 0, 100), vec![1, 2, 3]);
+        assert!(shift(&[], 0, 120).is_empty());
+
+        let slow = shift(&tone, 0, 80);
+        assert!((slow.len() as f32 - RATE as f32 / 0.8).abs() < 10.0);
+        assert!((frequency(&slow) - 440.0).abs() < 10.0);
+
+        //Key and tempo together
+        let both = shift(&tone, -6, 120);
+        assert!((frequency(&both) - 440.0 * ratio(-6)).abs() < 10.0);
+    }
+}
@@ -0,0 +1,430 @@
+use karaoke::{
+    history::{History, PlayOutcome, HISTORY},
+    queue::{open_queue, PlayQueue, QueueEntry},
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    pub static ref PLAYBACK: Arc<Mutex<Playback>> =
+        Arc::from(Mutex::from(Playback::new(open_queue(), HISTORY.clone())));
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerState {
+    //Nothing playing or queued
+    #[serde(rename = "idle")]
+    Idle,
+    //Song taken by the player, which hasn't started it yet
+    #[serde(rename = "loading")]
+    Loading,
+    #[serde(rename = "playing")]
+    Playing,
+    #[serde(rename = "paused")]
+    Paused,
+    //Waiting for the player to stop the last song or take the next one
+    #[serde(rename = "between_songs")]
+    BetweenSongs,
+}
+
+/// What the player has to do after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerAction {
+    Stop,
+    Pause,
+    Resume,
+    Seek(u32),
+    Key(i8),
+    Tempo(u8),
+}
+
+/// The queue, the song playing and what the player is doing with it. Commands
+/// from the site change it and say what the player has to do. The player takes
+/// songs from it and reports how they went. The native and web player both go
+/// through here, so the queue looks the same whichever is playing.
+#[derive(Debug)]
+pub struct Playback {
+    state: PlayerState,
+    queue: PlayQueue,
+    history: Arc<Mutex<History>>,
+    //Milliseconds into the song playing, as last reported by the player
+    elapsed: u64,
+}
+
+impl Playback {
+    pub fn new(queue: PlayQueue, history: Arc<Mutex<History>>) -> Playback {
+        let state = if queue.is_empty() {
+            PlayerState::Idle
+        } else {
+            PlayerState::BetweenSongs
+        };
+        Playback {
+            state,
+            queue,
+            history,
+            elapsed: 0,
+        }
+    }
+
+    pub fn state(&self) -> PlayerState {
+        self.state
+    }
+
+    /// Songs still to play, the one playing isn't in it
+    pub fn queue(&self) -> &PlayQueue {
+        &self.queue
+    }
+
+    pub fn playing(&self) -> Option<&QueueEntry> {
+        self.queue.playing()
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn add(&mut self, entry: QueueEntry) {
+        self.queue.add(entry);
+        self.settle();
+    }
+
+    /// Plays a song straight away, skipping the one playing
+    pub fn play_now(&mut self, entry: QueueEntry) -> Option<PlayerAction> {
+        self.queue.push_front(entry);
+        self.settle();
+        self.skip()
+    }
+
+    /// Skips the song playing
+    pub fn next(&mut self) -> Option<PlayerAction> {
+        self.skip()
+    }
+
+    /// Clears the queue and stops the song playing
+    pub fn stop(&mut self) -> Option<PlayerAction> {
+        self.queue.clear();
+        self.settle();
+        self.skip()
+    }
+
+    pub fn pause(&mut self) -> Option<PlayerAction> {
+        if self.state != PlayerState::Playing {
+            return None;
+        }
+        self.state = PlayerState::Paused;
+        Some(PlayerAction::Pause)
+    }
+
+    pub fn resume(&mut self) -> Option<PlayerAction> {
+        if self.state != PlayerState::Paused {
+            return None;
+        }
+        self.state = PlayerState::Playing;
+        Some(PlayerAction::Resume)
+    }
+
+    /// Jumps to `secs` into the song playing, which stays paused if it was.
+    /// Seeking past the end of a song with a known length goes to its end.
+    pub fn seek(&mut self, secs: u32) -> Option<PlayerAction> {
+        match self.state {
+            PlayerState::Playing | PlayerState::Paused => {
+                let duration = self.queue.playing()?.kfile.duration;
+                let secs = duration.map_or(secs, |duration| secs.min(duration));
+                self.elapsed = u64::from(secs) * 1000;
+                Some(PlayerAction::Seek(secs))
+            }
+            _ => None,
+        }
+    }
+
+    /// Changes the key of a queued song, or the one playing
+    pub fn set_key(&mut self, id: u64, key: i8) -> Option<PlayerAction> {
+        if !self.queue.set_key(id, key) {
+            return None;
+        }
+        self.playing_entry(id)
+            .map(|entry| PlayerAction::Key(entry.key))
+    }
+
+    /// Changes the tempo of a queued song, or the one playing
+    pub fn set_tempo(&mut self, id: u64, tempo: u8) -> Option<PlayerAction> {
+        if !self.queue.set_tempo(id, tempo) {
+            return None;
+        }
+        self.playing_entry(id)
+            .map(|entry| PlayerAction::Tempo(entry.tempo))
+    }
+
+    //The entry with `id` if it's the one the player has
+    fn playing_entry(&self, id: u64) -> Option<&QueueEntry> {
+        match (self.state, self.queue.playing()) {
+            (PlayerState::Loading, Some(entry))
+            | (PlayerState::Playing, Some(entry))
+            | (PlayerState::Paused, Some(entry))
+                if entry.id == id =>
+            {
+                Some(entry)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.settle();
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.queue.remove(id);
+        self.settle();
+    }
+
+    pub fn move_to(&mut self, id: u64, position: usize) {
+        self.queue.move_to(id, position);
+    }
+
+    pub fn swap(&mut self, id: u64, other: u64) {
+        self.queue.swap(id, other);
+    }
+
+    /// The song for the player to play once it's free. A player asking again
+    /// while the song is loading or playing lost it, and gets it again.
+    pub fn next_song(&mut self) -> Option<QueueEntry> {
+        match self.state {
+            PlayerState::Loading | PlayerState::Playing | PlayerState::Paused => {
+                self.state = PlayerState::Loading;
+                self.elapsed = 0;
+                self.queue.playing().cloned()
+            }
+            PlayerState::BetweenSongs if self.queue.playing().is_some() => None,
+            _ => {
+                let entry = self.queue.next()?;
+                self.queue.set_playing(Some(entry.clone()));
+                self.state = PlayerState::Loading;
+                self.elapsed = 0;
+                Some(entry)
+            }
+        }
+    }
+
+    pub fn started(&mut self) {
+        if self.state != PlayerState::Loading {
+            return;
+        }
+        if let Some(entry) = self.queue.playing() {
+            self.history.lock().unwrap().start(entry);
+            self.state = PlayerState::Playing;
+        }
+    }
+
+    pub fn paused(&mut self) {
+        if self.state == PlayerState::Playing {
+            self.state = PlayerState::Paused;
+        }
+    }
+
+    pub fn resumed(&mut self) {
+        if self.state == PlayerState::Paused {
+            self.state = PlayerState::Playing;
+        }
+    }
+
+    /// How far into the song with entry `id` the player is, in milliseconds.
+    /// Reports come with the entry id, so a late one for the last song is
+    /// ignored.
+    pub fn progress(&mut self, id: u64, elapsed: u64) {
+        if !self.is_playing(id) {
+            return;
+        }
+        if let PlayerState::Playing | PlayerState::Paused = self.state {
+            self.elapsed = elapsed;
+        }
+    }
+
+    /// The song played to the end. `played` is how far into it the player got
+    /// in milliseconds, if it knows.
+    pub fn ended(&mut self, id: u64, played: Option<u64>) {
+        self.finish(id, PlayOutcome::Finished, played);
+    }
+
+    /// The song was stopped before the end, by a command or at the player
+    pub fn stopped(&mut self, id: u64, played: Option<u64>) {
+        self.finish(id, PlayOutcome::Skipped, played);
+    }
+
+    fn is_playing(&self, id: u64) -> bool {
+        self.queue.playing().map(|entry| entry.id) == Some(id)
+    }
+
+    fn finish(&mut self, id: u64, outcome: PlayOutcome, played: Option<u64>) {
+        if !self.is_playing(id) {
+            return;
+        }
+        self.history.lock().unwrap().finish(outcome, played);
+        self.queue.set_playing(None);
+        self.state = PlayerState::BetweenSongs;
+        self.elapsed = 0;
+        self.settle();
+    }
+
+    //The song playing is kept until the player says it's stopped
+    fn skip(&mut self) -> Option<PlayerAction> {
+        match self.state {
+            PlayerState::Loading | PlayerState::Playing | PlayerState::Paused => {
+                self.state = PlayerState::BetweenSongs;
+                Some(PlayerAction::Stop)
+            }
+            _ => None,
+        }
+    }
+
+    //With nothing playing, the state only depends on whether songs are queued
+    fn settle(&mut self) {
+        let waiting = match self.state {
+            PlayerState::Idle => true,
+            PlayerState::BetweenSongs => self.queue.playing().is_none(),
+            _ => false,
+        };
+        if waiting {
+            self.state = if self.queue.is_empty() {
+                PlayerState::Idle
+            } else {
+                PlayerState::BetweenSongs
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::test_util::{queued, song, SONG_SECS};
+
+    fn entry(id: u64) -> QueueEntry {
+        queued(song(id, "", ""), None)
+    }
+
+    fn song_id(entry: Option<QueueEntry>) -> Option<u64> {
+        entry.map(|entry| entry.kfile.id)
+    }
+
+    #[test]
+    fn test_playback() {
+        let history = Arc::new(Mutex::new(History::default()));
+        let mut playback = Playback::new(PlayQueue::new(false), history);
+        assert_eq!(playback.state(), PlayerState::Idle);
+        assert_eq!(playback.next_song(), None);
+
+        playback.add(entry(1));
+        playback.add(entry(2));
+        assert_eq!(playback.state(), PlayerState::BetweenSongs);
+        assert_eq!(song_id(playback.next_song()), Some(1));
+        assert_eq!(playback.state(), PlayerState::Loading);
+        //A player that lost the song gets it again
+        assert_eq!(song_id(playback.next_song()), Some(1));
+        let first = playback.playing().unwrap().id;
+        playback.progress(first, 1000);
+        assert_eq!(playback.elapsed(), 0);
+        playback.started();
+        playback.progress(first, 1000);
+        playback.paused();
+        assert_eq!(playback.state(), PlayerState::Paused);
+        playback.resumed();
+        assert_eq!(playback.state(), PlayerState::Playing);
+        assert_eq!(playback.queue().entries().len(), 1);
+        assert_eq!(playback.elapsed(), 1000);
+
+        //Pausing and seeking only apply to a song that's started
+        assert_eq!(playback.resume(), None);
+        assert_eq!(playback.pause(), Some(PlayerAction::Pause));
+        assert_eq!(playback.pause(), None);
+        assert_eq!(playback.seek(30), Some(PlayerAction::Seek(30)));
+        assert_eq!(playback.state(), PlayerState::Paused);
+        assert_eq!(playback.elapsed(), 30_000);
+        assert_eq!(playback.resume(), Some(PlayerAction::Resume));
+        let playing = playback.playing().unwrap().id;
+        assert_eq!(playback.set_key(playing, -2), Some(PlayerAction::Key(-2)));
+        let queued = playback.queue().entries()[0].id;
+        assert_eq!(playback.set_key(queued, 3), None);
+        assert_eq!(playback.queue().entries()[0].key, 3);
+        assert_eq!(
+            playback.set_tempo(playing, 90),
+            Some(PlayerAction::Tempo(90))
+        );
+        assert_eq!(playback.set_tempo(queued, 110), None);
+        assert_eq!(playback.queue().entries()[0].tempo, 110);
+
+        //Skipping waits for the player to stop, then moves on
+        assert_eq!(playback.next(), Some(PlayerAction::Stop));
+        assert_eq!(playback.next(), None);
+        assert_eq!(playback.state(), PlayerState::BetweenSongs);
+        assert_eq!(playback.next_song(), None);
+        playback.stopped(first, Some(1000));
+        assert_eq!(playback.elapsed(), 0);
+        assert_eq!(song_id(playback.next_song()), Some(2));
+        playback.started();
+
+        //Late reports about the last song don't touch the next one
+        playback.progress(first, 5000);
+        assert_eq!(playback.elapsed(), 0);
+        playback.ended(first, None);
+        assert_eq!(playback.state(), PlayerState::Playing);
+        let second = playback.playing().unwrap().id;
+
+        //Play now goes ahead of the queue
+        playback.add(entry(3));
+        assert_eq!(playback.play_now(entry(4)), Some(PlayerAction::Stop));
+        playback.stopped(second, None);
+        assert_eq!(song_id(playback.next_song()), Some(4));
+        playback.started();
+        let fourth = playback.playing().unwrap().id;
+        playback.ended(fourth, None);
+        assert_eq!(playback.state(), PlayerState::BetweenSongs);
+
+        //With nothing playing, play now has nothing to stop
+        assert_eq!(playback.play_now(entry(5)), None);
+        let queued: Vec<u64> = playback
+            .queue()
+            .entries()
+            .iter()
+            .map(|entry| entry.kfile.id)
+            .collect();
+        assert_eq!(queued, vec![5, 3]);
+        playback.clear();
+        assert_eq!(playback.state(), PlayerState::Idle);
+        assert_eq!(playback.seek(0), None);
+
+        //Stop clears the queue too
+        playback.add(entry(6));
+        playback.add(entry(7));
+        let sixth = playback.next_song().unwrap().id;
+        playback.started();
+        assert_eq!(playback.stop(), Some(PlayerAction::Stop));
+        playback.stopped(sixth, None);
+        assert_eq!(playback.state(), PlayerState::Idle);
+        assert!(playback.queue().is_empty());
+    }
+
+    #[test]
+    fn test_seek_past_end() {
+        let history = Arc::new(Mutex::new(History::default()));
+        let mut playback = Playback::new(PlayQueue::new(false), history);
+        playback.add(entry(1));
+        playback.next_song();
+        playback.started();
+        assert_eq!(playback.seek(600), Some(PlayerAction::Seek(SONG_SECS)));
+        assert_eq!(playback.elapsed(), u64::from(SONG_SECS) * 1000);
+        assert_eq!(playback.seek(60), Some(PlayerAction::Seek(60)));
+
+        //A song of unknown length is left to the player
+        let mut unknown = entry(2);
+        unknown.kfile.duration = None;
+        playback.add(unknown);
+        playback.next();
+        playback.stopped(playback.playing().unwrap().id, None);
+        playback.next_song();
+        playback.started();
+        assert_eq!(playback.seek(600), Some(PlayerAction::Seek(600)));
+    }
+}
@@ -1,20 +1,22 @@
-use crossbeam_channel::{select, Receiver, Sender};
+use crossbeam_channel::{select, Receiver};
 use glium::{glutin, Surface};
-use glutin::{Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use glutin::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use image::GenericImage;
 use karaoke::{
-    channel::{LiveCommand, PlayerCommand, LIVE_CHANNEL, PLAYER_CHANNEL},
+    channel::{LiveCommand, LIVE_CHANNEL},
     collection::Kfile,
     embed::Assets,
-    queue::PLAY_QUEUE,
+    log_error,
+    pitch::{stretch, Controls},
+    playback::{Playback, PLAYBACK},
+    queue::QueueEntry,
     CONFIG,
 };
 use rodio::{Sink, Source};
 use std::{
     cell::RefCell,
     f32::consts,
-    fs::File,
-    io::{BufReader, Cursor},
+    io::Cursor,
     rc::Rc,
     sync::{
         atomic::{AtomicUsize, Ordering::SeqCst},
@@ -31,19 +33,9 @@ pub fn run() {
     });
 }
 
-#[derive(Eq, PartialEq, Debug)]
-pub enum PlayerStatus {
-    Playing,
-    Stopped,
-}
-
 pub struct Player {
-    pub status: Rc<RefCell<PlayerStatus>>,
-    pub player_sender: Sender<PlayerCommand>,
-    pub player_receiver: Receiver<PlayerCommand>,
-    pub live_sender: Sender<LiveCommand>,
     pub live_receiver: Receiver<LiveCommand>,
-    pub queue: Arc<Mutex<Vec<Kfile>>>,
+    pub playback: Arc<Mutex<Playback>>,
     pub events_loop: Rc<RefCell<glutin::EventsLoop>>,
     pub display: glium::Display,
     pub dimensions: glutin::dpi::LogicalSize,
@@ -52,9 +44,6 @@ pub struct Player {
 
 impl Player {
     pub fn new() -> Self {
-        let status = Rc::from(RefCell::from(PlayerStatus::Stopped));
-        let queue = PLAY_QUEUE.clone();
-
         //Setup event loop & display
         let events_loop = glutin::EventsLoop::new();
         let wb = glutin::WindowBuilder::new()
@@ -82,12 +71,8 @@ impl Player {
         let background = glium::texture::Texture2d::new(&display, image).unwrap();
 
         Player {
-            status,
-            player_sender: PLAYER_CHANNEL.0.clone(),
-            player_receiver: PLAYER_CHANNEL.1.clone(),
-            live_sender: LIVE_CHANNEL.0.clone(),
             live_receiver: LIVE_CHANNEL.1.clone(),
-            queue,
+            playback: PLAYBACK.clone(),
             events_loop: Rc::from(RefCell::from(events_loop)),
             display,
             dimensions,
@@ -99,10 +84,7 @@ impl Player {
         self.clear_background().unwrap();
 
         loop {
-            select! {
-                recv(self.player_receiver) -> cmd => self.process_cmd(cmd.unwrap()),
-                default() => self.check_queue(),
-            };
+            self.check_queue();
             std::thread::sleep(Duration::from_millis(50));
 
             self.events_loop.borrow_mut().poll_events(|event| {
@@ -135,63 +117,43 @@ impl Player {
         Ok(())
     }
 
-    pub fn stop(&self) {
-        self.live_sender.send(LiveCommand::Stop).unwrap();
-    }
-
     pub fn check_queue(&self) {
-        let mut queue = self.queue.lock().unwrap();
-        if queue.is_empty() {
-            drop(queue);
-            return;
-        }
-        let kfile = queue.remove(0);
-        drop(queue);
-        self.play(kfile);
-    }
-
-    pub fn play(&self, kfile: Kfile) {
-        std::thread::sleep(Duration::from_millis(100));
-        if *self.status.borrow() == PlayerStatus::Playing {
-            self.stop();
-        }
-        std::thread::sleep(Duration::from_millis(100));
-        self.empty_stale_live();
-        self.play_song(kfile).unwrap();
-    }
+        let mut playback = self.playback.lock().unwrap();
+        //Commands sent for the last song after it stopped are stale. Any for
+        //the next one are sent with the playback locked, so can't be among them.
+        while self.live_receiver.try_recv().is_ok() {}
+        let entry = match playback.next_song() {
+            Some(entry) => entry,
+            None => return,
+        };
+        drop(playback);
 
-    fn process_cmd(&self, cmd: PlayerCommand) {
-        match cmd {
-            PlayerCommand::Play { kfile } => self.play(kfile),
+        if let Err(e) = self.play_song(&entry) {
+            log_error(&e);
+            self.playback.lock().unwrap().stopped(entry.id, None);
+            self.clear_background().unwrap();
         }
     }
 
-    fn empty_stale_live(&self) {
-        select! {
-            recv(self.live_receiver) -> _ => { },
-            default() => { },
-        };
-    }
+    fn play_song(&self, entry: &QueueEntry) -> Result<(), failure::Error> {
+        let kfile = &entry.kfile;
 
-    fn play_song(&self, kfile: Kfile) -> Result<(), failure::Error> {
-        *self.status.borrow_mut() = PlayerStatus::Playing;
-
-        //Create new output device, load mp3 into sound buffer, decode with rodio, setup periodic access
+        //Create new output device, load audio into sound buffer, decode with rodio, setup periodic access
         //to callback everytime 1ms has passed to track song position for synchronization
         let device = rodio::default_output_device().unwrap();
-        let sink = Sink::new(&device);
-        let file = File::open(&kfile.mp3_path)?;
+        let mut sink = Sink::new(&device);
         let counter = Arc::from(AtomicUsize::new(0));
-        let periodic_counter = counter.clone();
-        let access_time = Duration::from_millis(1);
-        let source =
-            rodio::Decoder::new(BufReader::new(file))?.periodic_access(access_time, move |_| {
-                let _ = periodic_counter.fetch_add(1, SeqCst);
-            });
+        let controls = Arc::from(Controls::new(entry.key, entry.tempo));
+        let source = open_source(
+            kfile,
+            Duration::from_secs(0),
+            counter.clone(),
+            controls.clone(),
+        )?;
 
         //Load cdg, create Subchannel Iterator to cycle through cdg sectors
-        let cdg = File::open(&kfile.cdg_path)?;
-        let mut scsi = cdg::SubchannelStreamIter::new(BufReader::new(cdg));
+        let cdg = kfile.open_cdg()?;
+        let mut scsi = cdg::SubchannelStreamIter::new(cdg);
 
         //Size of cdg render texture, scaled at 1.5x (default)
         let cdg_x: f32 = 300.0;
@@ -228,6 +190,9 @@ impl Player {
         let mut last_sector_no: isize = 0;
         let mut sectors_since: isize = 0;
 
+        //Position is reported to the playback once a second
+        let mut last_reported: usize = 0;
+
         //Create CdgInterpreter, which will consume sector commands and produce
         //finished frames which can be copied into RgbaImage. Image data can then
         //be fed into renderable in-GPU-memory image
@@ -236,6 +201,8 @@ impl Player {
 
         //Play it!
         sink.append(source);
+        self.playback.lock().unwrap().started();
+        let mut finished = false;
 
         //Loop will get current song position, calculate how many "cdg sectors"
         //have elasped in total (1 sector = 1/75th of a second), and subtract
@@ -248,9 +215,16 @@ impl Player {
         //rainbow.
         //
         //Current song can be stopped with either ESC key or receiving a Stop
-        //command.
+        //command, and paused with the space bar.
         'player: loop {
-            let track_pos = counter.load(SeqCst);
+            let track_pos = counter.load(SeqCst) / 1000;
+            if track_pos / 1000 != last_reported {
+                last_reported = track_pos / 1000;
+                self.playback
+                    .lock()
+                    .unwrap()
+                    .progress(entry.id, track_pos as u64);
+            }
 
             //Offset rendering lyrics by 20 sectors, this syncs lyrics to music
             //almost perfectly
@@ -269,6 +243,7 @@ impl Player {
                             cdg_interp.handle_cmd(cmd);
                         }
                     } else {
+                        finished = true;
                         break 'player;
                     }
                 }
@@ -321,6 +296,7 @@ impl Player {
 
             //Quit song if ESC key pressed
             let mut _break = false;
+            let mut _pause = false;
             self.events_loop.borrow_mut().poll_events(|event| {
                 if let Event::WindowEvent { event, .. } = event {
                     match event {
@@ -330,15 +306,16 @@ impl Player {
                         WindowEvent::KeyboardInput {
                             input:
                                 KeyboardInput {
+                                    state: ElementState::Pressed,
                                     virtual_keycode: Some(keycode),
                                     ..
                                 },
                             ..
-                        } => {
-                            if let VirtualKeyCode::Escape = keycode {
-                                _break = true;
-                            }
-                        }
+                        } => match keycode {
+                            VirtualKeyCode::Escape => _break = true,
+                            VirtualKeyCode::Space => _pause = true,
+                            _ => (),
+                        },
                         _ => (),
                     }
                 }
@@ -346,26 +323,105 @@ impl Player {
             if _break {
                 break 'player;
             };
+            if _pause {
+                let mut playback = self.playback.lock().unwrap();
+                if sink.is_paused() {
+                    sink.play();
+                    playback.resumed();
+                } else {
+                    sink.pause();
+                    playback.paused();
+                }
+            }
 
             //Check to see if Stop command is received for early exit
             select! {
                 recv(self.live_receiver) -> cmd => {
-                    if cmd.unwrap() == LiveCommand::Stop {
-                        break 'player;
+                    match cmd.unwrap() {
+                        LiveCommand::Stop => break 'player,
+                        LiveCommand::Pause => sink.pause(),
+                        LiveCommand::Resume => sink.play(),
+                        LiveCommand::Seek { secs } => {
+                            //A sink can't be rewound, so the song is decoded
+                            //again into a new one, paused if the old one was
+                            let paused = sink.is_paused();
+                            sink = Sink::new(&device);
+                            if paused {
+                                sink.pause();
+                            }
+                            let start = Duration::from_secs(u64::from(secs));
+                            sink.append(open_source(
+                                kfile,
+                                start,
+                                counter.clone(),
+                                controls.clone(),
+                            )?);
+
+                            //Cdg commands draw on what's already there, so the
+                            //lyrics are redrawn from the start up to the new
+                            //position
+                            scsi = cdg::SubchannelStreamIter::new(kfile.open_cdg()?);
+                            cdg_interp = cdg_renderer::CdgInterpreter::new();
+                            last_sector_no = 0;
+                        }
+                        LiveCommand::Key { key } => controls.set_key(key),
+                        LiveCommand::Tempo { tempo } => controls.set_tempo(tempo),
                     }
                 },
                 default => {},
             }
 
+            //Audio that runs out before the cdg, or a seek past the end of it,
+            //leaves the sink empty and the counter stopped, so that ends the
+            //song too
+            if sink.empty() {
+                finished = true;
+                break 'player;
+            }
+
             //Save some CPU time
             std::thread::sleep(Duration::from_millis(10));
         }
-        *self.status.borrow_mut() = PlayerStatus::Stopped;
+        let played = Some(counter.load(SeqCst) as u64 / 1000);
+        let mut playback = self.playback.lock().unwrap();
+        if finished {
+            playback.ended(entry.id, played);
+        } else {
+            playback.stopped(entry.id, played);
+        }
+        drop(playback);
+
         self.clear_background().unwrap();
         Ok(())
     }
 }
 
+//Decodes the song's audio from `start`, played in the key and at the tempo of
+//`controls`. `counter` keeps how far into the song it is in microseconds, which
+//goes faster or slower than the audio played with the tempo. A `start` past the
+//end leaves nothing to play.
+fn open_source(
+    kfile: &Kfile,
+    start: Duration,
+    counter: Arc<AtomicUsize>,
+    controls: Arc<Controls>,
+) -> Result<impl Source<Item = i16> + Send, failure::Error> {
+    let mut decoder = rodio::Decoder::new(kfile.open_audio()?)?;
+    let start_ms = start.as_millis() as u64;
+    let samples =
+        start_ms * u64::from(decoder.sample_rate()) * u64::from(decoder.channels()) / 1000;
+    decoder.by_ref().take(samples as usize).for_each(drop);
+
+    counter.store(start_ms as usize * 1000, SeqCst);
+    let access_time = Duration::from_millis(1);
+    Ok(
+        stretch(decoder, controls.clone()).periodic_access(access_time, move |_| {
+            //Percent of a millisecond of the song played in this one
+            let _ = counter.fetch_add(usize::from(controls.tempo()) * 10, SeqCst);
+        }),
+    )
+}
+
 //Sine wave formula for rainbow cycling background color
 fn rainbow_cycle(i: &mut f32, size: f32) -> (f32, f32, f32, f32) {
     *i = if (*i + 1.0) % size == 0.0 {
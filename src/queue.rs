@@ -1,7 +1,453 @@
-use karaoke::collection::Kfile;
-use lazy_static::lazy_static;
-use std::sync::{Arc, Mutex};
+use karaoke::{
+    collection::Kfile,
+    log_error,
+    pitch::{MAX_KEY, MAX_TEMPO, MIN_TEMPO},
+    store::QueueDB,
+    CONFIG,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-lazy_static! {
-    pub static ref PLAY_QUEUE: Arc<Mutex<Vec<Kfile>>> = { Arc::from(Mutex::from(Vec::new())) };
+/// Queue saved to the data dir, picking up the last run's if the config says so
+pub fn open_queue() -> PlayQueue {
+    let mut queue = PlayQueue::new(CONFIG.queue.rotation);
+    let attached =
+        QueueDB::open(&CONFIG.data_path).and_then(|db| queue.attach(db, CONFIG.queue.restore));
+    if let Err(e) = attached {
+        log_error(&e);
+    }
+    queue
+}
+
+//Songs without a known length are estimated to take this long
+const DEFAULT_SONG_SECS: u32 = 240;
+
+/// A queued song. The id is given by the queue when the song is added and
+/// stays with it as the queue changes, so edits can't hit the wrong song.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    #[serde(default)]
+    pub id: u64,
+    pub kfile: Kfile,
+    pub singer: Option<String>,
+    //Semitones up or down the song is played in
+    #[serde(default)]
+    pub key: i8,
+    //Percent of the song's normal tempo it's played at
+    #[serde(default = "normal_tempo")]
+    pub tempo: u8,
+}
+
+fn normal_tempo() -> u8 {
+    100
+}
+
+impl QueueEntry {
+    pub fn new(kfile: Kfile, singer: Option<String>) -> QueueEntry {
+        let singer = singer
+            .map(|singer| singer.trim().to_owned())
+            .filter(|singer| !singer.is_empty());
+        QueueEntry {
+            id: 0,
+            kfile,
+            singer,
+            key: 0,
+            tempo: normal_tempo(),
+        }
+    }
+
+    //Songs queued without a name all count as one singer
+    fn singer_key(&self) -> String {
+        self.singer.clone().unwrap_or_default().to_lowercase()
+    }
+}
+
+/// Songs waiting to be played, in order, and the one playing. In rotation mode
+/// singers take turns: a song goes after everyone else's song in the same turn,
+/// so one singer queueing several songs can't hold up the rest. The singer of
+/// the song playing has had their turn.
+#[derive(Debug, Default)]
+pub struct PlayQueue {
+    entries: Vec<QueueEntry>,
+    //Song taken off the queue by the player
+    playing: Option<QueueEntry>,
+    rotation: bool,
+    last_id: u64,
+    db: Option<QueueDB>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedQueue {
+    entries: Vec<QueueEntry>,
+    playing: Option<QueueEntry>,
+}
+
+impl PlayQueue {
+    pub fn new(rotation: bool) -> PlayQueue {
+        PlayQueue {
+            rotation,
+            ..PlayQueue::default()
+        }
+    }
+
+    /// Saves the queue to `db` on every change from now on. Each save is a
+    /// snapshot of the whole queue and the song playing, which is small enough
+    /// to rewrite every time, rather than a log of changes.
+    ///
+    /// With `restore`, the songs saved by the last run are queued again, the
+    /// one that was playing first. Songs whose files are gone are dropped.
+    /// Without it, what was saved is kept until the queue first changes, so
+    /// it's still there if the host turns restoring on and restarts.
+    pub fn attach(&mut self, db: QueueDB, restore: bool) -> Result<(), failure::Error> {
+        if restore {
+            if let Some(saved) = db.load::<SavedQueue>()? {
+                let saved: Vec<QueueEntry> =
+                    saved.playing.into_iter().chain(saved.entries).collect();
+                let count = saved.len();
+                self.entries = saved
+                    .into_iter()
+                    .filter(|entry| entry.kfile.files_exist())
+                    .collect();
+                self.last_id = self.entries.iter().map(|entry| entry.id).max().unwrap_or(0);
+                for index in 0..self.entries.len() {
+                    if self.entries[index].id == 0 {
+                        self.entries[index].id = self.next_id();
+                    }
+                }
+                log::info!(
+                    "Queue restored: {} songs, {} missing dropped",
+                    self.entries.len(),
+                    count - self.entries.len()
+                );
+            }
+        }
+        self.db = Some(db);
+        if restore {
+            self.save();
+        }
+        Ok(())
+    }
+
+    fn save(&self) {
+        if let Some(db) = &self.db {
+            let saved = SavedQueue {
+                entries: self.entries.clone(),
+                playing: self.playing.clone(),
+            };
+            if let Err(e) = db.save(&saved) {
+                log_error(&e);
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn position(&self, id: u64) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    pub fn add(&mut self, mut entry: QueueEntry) {
+        entry.id = self.next_id();
+        if !self.rotation {
+            self.entries.push(entry);
+            self.save();
+            return;
+        }
+
+        //The turn of a song is how many songs its singer has ahead of it. The
+        //new song takes the singer's next turn, after every song in that turn.
+        let singer = entry.singer_key();
+        let turn = self
+            .entries
+            .iter()
+            .chain(self.playing.as_ref())
+            .filter(|queued| queued.singer_key() == singer)
+            .count();
+        let position = self
+            .turns()
+            .iter()
+            .position(|queued_turn| *queued_turn > turn)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(position, entry);
+        self.save();
+    }
+
+    //Turn of each song in the queue, the song playing took its singer's first
+    fn turns(&self) -> Vec<usize> {
+        let mut counts = HashMap::new();
+        if let Some(playing) = &self.playing {
+            counts.insert(playing.singer_key(), 1);
+        }
+        self.entries
+            .iter()
+            .map(|entry| {
+                let count = counts.entry(entry.singer_key()).or_insert(0);
+                *count += 1;
+                *count - 1
+            })
+            .collect()
+    }
+
+    /// Takes the next song off the queue
+    pub fn next(&mut self) -> Option<QueueEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let entry = self.entries.remove(0);
+        self.save();
+        Some(entry)
+    }
+
+    /// Puts a song at the front, to be played next
+    pub fn push_front(&mut self, mut entry: QueueEntry) {
+        entry.id = self.next_id();
+        self.entries.insert(0, entry);
+        self.save();
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<QueueEntry> {
+        let entry = self.entries.remove(self.position(id)?);
+        self.save();
+        Some(entry)
+    }
+
+    /// Moves a song to `position`, or to the end if that's past it. Returns
+    /// false if the song isn't queued.
+    pub fn move_to(&mut self, id: u64, position: usize) -> bool {
+        let entry = match self.remove(id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let position = position.min(self.entries.len());
+        self.entries.insert(position, entry);
+        self.save();
+        true
+    }
+
+    /// Swaps two songs, returns false unless both are queued
+    pub fn swap(&mut self, id: u64, other: u64) -> bool {
+        match (self.position(id), self.position(other)) {
+            (Some(a), Some(b)) => {
+                self.entries.swap(a, b);
+                self.save();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Changes the key of a queued song, or the one playing
+    pub fn set_key(&mut self, id: u64, key: i8) -> bool {
+        self.edit_entry(id, |entry| entry.key = key.clamp(-MAX_KEY, MAX_KEY))
+    }
+
+    /// Changes the tempo of a queued song, or the one playing
+    pub fn set_tempo(&mut self, id: u64, tempo: u8) -> bool {
+        self.edit_entry(id, |entry| entry.tempo = tempo.clamp(MIN_TEMPO, MAX_TEMPO))
+    }
+
+    fn edit_entry<F>(&mut self, id: u64, edit: F) -> bool
+    where
+        F: FnOnce(&mut QueueEntry),
+    {
+        let entry = self
+            .entries
+            .iter_mut()
+            .chain(self.playing.as_mut())
+            .find(|entry| entry.id == id);
+        match entry {
+            Some(entry) => {
+                edit(entry);
+                self.save();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.save();
+    }
+
+    pub fn playing(&self) -> Option<&QueueEntry> {
+        self.playing.as_ref()
+    }
+
+    pub fn set_playing(&mut self, entry: Option<QueueEntry>) {
+        self.playing = entry;
+        self.save();
+    }
+
+    /// Seconds from now until each song starts, after what's left of the song
+    /// playing, `elapsed` milliseconds into it. Songs take longer played
+    /// slower.
+    pub fn start_times(&self, elapsed: u64) -> Vec<u32> {
+        let mut start = match &self.playing {
+            Some(entry) => {
+                let played = (elapsed / 1000) as u32;
+                play_time(entry, played)
+            }
+            None => 0,
+        };
+        self.entries
+            .iter()
+            .map(|entry| {
+                let song_start = start;
+                start += play_time(entry, 0);
+                song_start
+            })
+            .collect()
+    }
+}
+
+//Seconds it takes to play a song from `from` seconds into it, at its tempo
+fn play_time(entry: &QueueEntry, from: u32) -> u32 {
+    let duration = entry.kfile.duration.unwrap_or(DEFAULT_SONG_SECS);
+    duration.saturating_sub(from) * 100 / u32::from(entry.tempo.max(MIN_TEMPO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::test_util::{queued, song, TestDir, SONG_PATH};
+    use std::path::PathBuf;
+
+    fn entry(id: u64, singer: &str) -> QueueEntry {
+        queued(song(id, "", ""), Some(singer))
+    }
+
+    fn order(queue: &PlayQueue) -> Vec<u64> {
+        queue.entries().iter().map(|entry| entry.kfile.id).collect()
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut queue = PlayQueue::new(true);
+        queue.add(entry(1, "Ann"));
+        queue.add(entry(2, "Ann"));
+        queue.add(entry(3, "Ann"));
+        queue.add(entry(4, "Bob"));
+        queue.add(entry(5, "Cat"));
+        //A later song slots into the singer's turn, names ignore case
+        queue.add(entry(6, "bob "));
+        assert_eq!(order(&queue), vec![1, 4, 5, 2, 6, 3]);
+
+        assert_eq!(queue.next().unwrap().kfile.id, 1);
+        queue.add(entry(7, "Cat"));
+        assert_eq!(order(&queue), vec![4, 5, 2, 6, 3, 7]);
+        assert_eq!(queue.entries()[0].singer, Some("Bob".to_owned()));
+        assert_eq!(queue.start_times(0)[..3], [0, 200, 400]);
+
+        //The singer playing has had their turn, and the time left of their
+        //song comes before the rest
+        let mut playing = entry(8, "Ann");
+        playing.tempo = 80;
+        queue.set_playing(Some(playing));
+        queue.add(entry(9, "Ann"));
+        queue.add(entry(10, "Dan"));
+        assert_eq!(order(&queue), vec![4, 5, 10, 2, 6, 3, 7, 9]);
+        assert_eq!(queue.start_times(40_000)[..3], [200, 400, 600]);
+        assert_eq!(queue.start_times(500_000)[0], 0);
+
+        //Without rotation it's first come, first served
+        let mut queue = PlayQueue::new(false);
+        queue.add(entry(1, "Ann"));
+        queue.add(entry(2, "Ann"));
+        queue.add(entry(3, "Bob"));
+        assert_eq!(order(&queue), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut queue = PlayQueue::new(false);
+        for id in 1..=5 {
+            queue.add(entry(id, "Ann"));
+        }
+        let ids: Vec<u64> = queue.entries().iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(queue.remove(2).unwrap().kfile.id, 2);
+        assert!(queue.remove(2).is_none());
+        assert_eq!(order(&queue), vec![1, 3, 4, 5]);
+
+        assert!(queue.move_to(5, 0));
+        assert!(queue.move_to(1, 10));
+        assert_eq!(order(&queue), vec![5, 3, 4, 1]);
+
+        assert!(queue.swap(3, 1));
+        assert!(!queue.swap(3, 2));
+        assert_eq!(order(&queue), vec![5, 1, 4, 3]);
+        assert!(queue.set_key(4, -9));
+        assert_eq!(queue.entries()[2].key, -6);
+        assert!(!queue.set_key(2, 1));
+        assert!(queue.set_tempo(5, 80));
+        assert!(queue.set_tempo(1, 150));
+        assert_eq!(queue.entries()[1].tempo, 120);
+        assert_eq!(queue.start_times(0), vec![0, 250, 416, 616]);
+
+        //Ids keep counting up, a removed song's id isn't reused
+        queue.next();
+        queue.add(entry(6, "Ann"));
+        assert_eq!(queue.entries()[3].id, 6);
+        assert_eq!(queue.position(6), Some(3));
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = TestDir::new("queue_db");
+        let data_path = dir.path();
+        let song = |id, name: &str| {
+            let kfile = Kfile {
+                audio_path: PathBuf::from(SONG_PATH).join(format!("{}.mp3", name)),
+                cdg_path: PathBuf::from(SONG_PATH).join(format!("{}.cdg", name)),
+                ..song(id, "", "")
+            };
+            queued(kfile, Some("Ann"))
+        };
+
+        let mut queue = PlayQueue::new(false);
+        queue
+            .attach(QueueDB::open(data_path).unwrap(), true)
+            .unwrap();
+        queue.add(song(1, "match"));
+        queue.add(song(2, "missing"));
+        queue.add(song(3, "match"));
+        let playing = queue.next();
+        queue.set_playing(playing);
+
+        //Nothing is picked up unless asked for, and what was saved is left
+        //for a later run that asks
+        let mut restored = PlayQueue::new(false);
+        restored
+            .attach(QueueDB::open(data_path).unwrap(), false)
+            .unwrap();
+        assert!(restored.is_empty());
+        let mut restored = PlayQueue::new(false);
+        restored
+            .attach(QueueDB::open(data_path).unwrap(), true)
+            .unwrap();
+        assert_eq!(order(&restored), vec![1, 3]);
+
+        queue.set_playing(Some(song(4, "match")));
+        let mut restored = PlayQueue::new(false);
+        restored
+            .attach(QueueDB::open(data_path).unwrap(), true)
+            .unwrap();
+        assert_eq!(order(&restored), vec![4, 3]);
+        //Ids carry on from the restored songs
+        restored.add(song(5, "match"));
+        assert_eq!(restored.entries()[2].id, 5);
+    }
 }
@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use strsim::osa_distance;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+//How well a query term matched a token, multiplied by the weight of the field
+//the token came from to score a match
+const EXACT_MATCH: u32 = 4;
+const PREFIX_MATCH: u32 = 2;
+const TYPO_MATCH: u32 = 1;
+
+/// Inverted index from normalized tokens to the documents (songs or artists)
+/// holding them, built once with the collection so a search doesn't scan it
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SearchIndex {
+    //Sorted, so tokens sharing a prefix sit next to each other
+    tokens: BTreeMap<String, Vec<Posting>>,
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+struct Posting {
+    id: u64,
+    weight: u32,
+}
+
+impl SearchIndex {
+    /// Indexes the tokens of `text` for the document `id`. Matches on fields
+    /// with a higher `weight` rank higher.
+    pub fn insert(&mut self, id: u64, text: &str, weight: u32) {
+        let posting = Posting { id, weight };
+        for token in tokenize(text) {
+            //A word repeated in the text is only indexed once
+            let postings = self.tokens.entry(token).or_default();
+            if postings.last() != Some(&posting) {
+                postings.push(posting);
+            }
+        }
+    }
+
+    /// Ids of the documents matching every term of the query, with their
+    /// relevance score. The last term can match the start of a token, as it's
+    /// likely still being typed, and longer terms can be misspelled.
+    pub fn search(&self, query: &str) -> Vec<(u64, u32)> {
+        let terms = tokenize(query);
+        let mut scores: Option<HashMap<u64, u32>> = None;
+
+        for (idx, term) in terms.iter().enumerate() {
+            let mut term_scores: HashMap<u64, u32> = HashMap::new();
+            for (postings, quality) in self.matches(term, idx == terms.len() - 1) {
+                for posting in postings {
+                    let score = term_scores.entry(posting.id).or_default();
+                    *score = (*score).max(quality * posting.weight);
+                }
+            }
+
+            scores = Some(match scores {
+                None => term_scores,
+                Some(mut scores) => {
+                    scores.retain(|id, _| term_scores.contains_key(id));
+                    for (id, score) in scores.iter_mut() {
+                        *score += term_scores[id];
+                    }
+                    scores
+                }
+            });
+        }
+
+        scores.unwrap_or_default().into_iter().collect()
+    }
+
+    //Postings of every token a term matches, with how well it matched
+    fn matches(&self, term: &str, prefix: bool) -> Vec<(&[Posting], u32)> {
+        let mut matches = Vec::new();
+        if let Some(postings) = self.tokens.get(term) {
+            matches.push((postings.as_slice(), EXACT_MATCH));
+        }
+        if prefix {
+            let longer = self
+                .tokens
+                .range::<str, _>((std::ops::Bound::Excluded(term), std::ops::Bound::Unbounded))
+                .take_while(|(token, _)| token.starts_with(term));
+            for (_, postings) in longer {
+                matches.push((postings.as_slice(), PREFIX_MATCH));
+            }
+        }
+
+        let typos = max_typos(term);
+        if typos > 0 {
+            let len = term.chars().count();
+            for (token, postings) in self.tokens.iter() {
+                let token_len = token.chars().count();
+                if token_len + typos < len || token_len > len + typos || token == term {
+                    continue;
+                }
+                //Swapped letters count as one typo, ie. "nigth"
+                if osa_distance(term, token) <= typos {
+                    matches.push((postings.as_slice(), TYPO_MATCH));
+                }
+            }
+        }
+
+        matches
+    }
+}
+
+//Short terms have to be spelled right, or they'd match most of the index
+fn max_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Lowercase words without accents or punctuation, ie. "Beyoncé - Don't Stop"
+/// -> ["beyonce", "dont", "stop"]
+pub fn tokenize(text: &str) -> Vec<String> {
+    words(text).into_iter().map(|(token, _)| token).collect()
+}
+
+/// Byte ranges of `text` matching a term of the query, for highlighting a
+/// result. A word matched by its start only has that part highlighted.
+pub fn highlights(text: &str, query: &str) -> Vec<(usize, usize)> {
+    let terms = tokenize(query);
+    let mut spans = Vec::new();
+
+    for (token, (start, end)) in words(text) {
+        let matched = terms.iter().enumerate().find_map(|(idx, term)| {
+            if *term == token {
+                Some(end)
+            } else if idx == terms.len() - 1 && token.starts_with(term.as_str()) {
+                Some(prefix_end(text, start, term.len()))
+            } else if max_typos(term) > 0 && osa_distance(term, &token) <= max_typos(term) {
+                Some(end)
+            } else {
+                None
+            }
+        });
+        if let Some(end) = matched {
+            spans.push((start, end));
+        }
+    }
+
+    spans
+}
+
+//Tokens with the byte range of the word they came from
+fn words(text: &str) -> Vec<(String, (usize, usize))> {
+    let mut words = Vec::new();
+    let mut token = String::new();
+    let mut start = 0;
+
+    for (idx, c) in text.char_indices() {
+        match fold(c) {
+            Some(folded) => {
+                if token.is_empty() && folded.is_empty() {
+                    continue;
+                }
+                if token.is_empty() {
+                    start = idx;
+                }
+                token.push_str(&folded);
+            }
+            None if !token.is_empty() => {
+                words.push((std::mem::take(&mut token), (start, idx)));
+            }
+            None => {}
+        }
+    }
+    if !token.is_empty() {
+        words.push((token, (start, text.len())));
+    }
+
+    words
+}
+
+//End of the shortest start of the word at `start` folding to `len` bytes
+fn prefix_end(text: &str, start: usize, len: usize) -> usize {
+    let mut folded = 0;
+    for (idx, c) in text[start..].char_indices() {
+        if folded >= len {
+            return start + idx;
+        }
+        folded += fold(c).map_or(0, |folded| folded.len());
+    }
+    text.len()
+}
+
+//What a character is indexed as, None if it separates words
+fn fold(c: char) -> Option<String> {
+    let mut folded = String::new();
+    for c in c.to_string().nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            //Apostrophes join a word back together, ie. "don't" -> "dont"
+            '\'' | '’' | '`' => {}
+            'ß' => folded.push_str("ss"),
+            'æ' | 'Æ' => folded.push_str("ae"),
+            'œ' | 'Œ' => folded.push_str("oe"),
+            'ø' | 'Ø' => folded.push('o'),
+            'ł' | 'Ł' => folded.push('l'),
+            'đ' | 'Đ' => folded.push('d'),
+            c if c.is_alphanumeric() => folded.extend(c.to_lowercase()),
+            _ => return None,
+        }
+    }
+    Some(folded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("Beyoncé"), vec!["beyonce"]);
+        assert_eq!(
+            tokenize("Don't Stop Me Now!"),
+            vec!["dont", "stop", "me", "now"]
+        );
+        assert_eq!(tokenize("AC/DC - Straße"), vec!["ac", "dc", "strasse"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::default();
+        index.insert(1, "Don't Stop Me Now", 2);
+        index.insert(1, "Queen", 1);
+        index.insert(2, "Halo", 2);
+        index.insert(2, "Beyoncé", 1);
+        index.insert(3, "Stop", 2);
+        index.insert(3, "Spice Girls", 1);
+
+        let ids = |query: &str| {
+            let mut results = index.search(query);
+            results.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            results.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids("beyonce"), vec![2]);
+        assert_eq!(ids("dont stop"), vec![1]);
+        assert_eq!(ids("queen don"), vec![1]);
+        //A misspelling still matches
+        assert_eq!(ids("beyonse"), vec![2]);
+        assert_eq!(ids("qeuen"), vec![1]);
+        assert_eq!(ids("stop"), vec![1, 3]);
+        assert_eq!(ids("stop spice"), vec![3]);
+        assert!(ids("stop halo").is_empty());
+        assert!(ids("").is_empty());
+    }
+
+    #[test]
+    fn test_highlights() {
+        let text = "Beyoncé - Don't Stop";
+        let spans = |query: &str| {
+            highlights(text, query)
+                .into_iter()
+                .map(|(start, end)| &text[start..end])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans("beyonce"), vec!["Beyoncé"]);
+        assert_eq!(spans("dont st"), vec!["Don't", "St"]);
+        assert_eq!(spans("beyon"), vec!["Beyon"]);
+        assert_eq!(spans("beyoncé sotp"), vec!["Beyoncé", "Stop"]);
+        assert!(spans("halo").is_empty());
+        assert!(spans("").is_empty());
+    }
+}
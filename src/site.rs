@@ -1,25 +1,112 @@
-use actix_web::{middleware, web, App, Error, HttpResponse, HttpServer};
+use actix_web::{
+    error::{self, BlockingError},
+    middleware, web, App, Error, HttpResponse, HttpServer,
+};
 use crossbeam_channel::Sender;
+use futures::{future, Future};
 use karaoke::{
     channel::{WorkerCommand, WORKER_CHANNEL},
     collection::{
-        add_favorite, calculate_hash, remove_favorite, Collection, Database, FavoritesDB, Kfile,
-        COLLECTION,
+        add_favorite, artist_credited, load_collection, remove_favorite, set_artist_override,
+        set_song_override, Artist, Collection, Database, Kfile, Overrides, OverridesDB,
+        SongOverride, COLLECTION,
     },
     config::Config,
-    queue::PLAY_QUEUE,
+    export::{self, ExportFormat},
+    health::{self, HealthCheck, HealthReport, HEALTH},
+    history::{self, History, Play, PlayCount, Session, HISTORY},
+    playback::{Playback, PlayerState, PLAYBACK},
+    queue::QueueEntry,
+    search::{highlights, SearchIndex},
+    store::{CollectionDB, FavoritesDB},
+    thumbnail::{self, PREVIEW_DIR},
     CONFIG,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::{
+    io::Read,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
 
 const PAGE_SIZE: usize = 100;
+//Suggestions returned by default, and at most
+const SUGGEST_SIZE: usize = 8;
+const MAX_SUGGEST_SIZE: usize = 25;
+//Songs and artists in the play stats by default, and at most
+const STATS_SIZE: usize = 10;
+const MAX_STATS_SIZE: usize = 100;
 
 #[derive(Deserialize)]
 struct Song {
     hash: u64,
 }
 
+#[derive(Deserialize)]
+struct AddSong {
+    hash: u64,
+    singer: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QueueRemove {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct QueueMove {
+    id: u64,
+    position: usize,
+}
+
+#[derive(Deserialize)]
+struct QueueSwap {
+    id: u64,
+    other: u64,
+}
+
+#[derive(Deserialize)]
+struct QueueKey {
+    id: u64,
+    key: i8,
+}
+
+#[derive(Deserialize)]
+struct QueueTempo {
+    id: u64,
+    tempo: u8,
+}
+
+#[derive(Deserialize)]
+struct Seek {
+    seconds: u32,
+}
+
+//Milliseconds into the song with entry `id`, reported by the web player
+#[derive(Deserialize)]
+struct Position {
+    id: u64,
+    elapsed: u64,
+}
+
+#[derive(Deserialize)]
+struct Ended {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct SongEdit {
+    hash: u64,
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArtistEdit {
+    artist: String,
+    name: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Queue {
     queue: Vec<Kfile>,
@@ -32,6 +119,83 @@ struct ResponseSong {
     artist_id: u64,
     artist_name: String,
     favorite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    library: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track: Option<String>,
+}
+
+impl ResponseSong {
+    fn new(id: u64, kfile: Kfile, favorite: bool) -> ResponseSong {
+        ResponseSong {
+            id,
+            name: kfile.song,
+            artist_id: kfile.artist_hash,
+            artist_name: kfile.artist,
+            favorite,
+            library: kfile.library,
+            duration: kfile.duration,
+            bitrate: kfile.bitrate,
+            genre: kfile.genre,
+            year: kfile.year,
+            language: kfile.language,
+            album: kfile.album,
+            disc: kfile.disc,
+            track: kfile.track,
+        }
+    }
+}
+
+//Start is an estimate in seconds from now
+#[derive(Serialize)]
+struct ResponseQueueEntry {
+    entry_id: u64,
+    #[serde(flatten)]
+    song: ResponseSong,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    singer: Option<String>,
+    start_in: u32,
+    key: i8,
+    tempo: u8,
+}
+
+//Times are in seconds, there's no song when the player is idle
+#[derive(Serialize)]
+struct ResponseNowPlaying {
+    state: PlayerState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    song: Option<ResponseSong>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    singer: Option<String>,
+    elapsed: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u32>,
+    key: i8,
+    tempo: u8,
+}
+
+//The last report, there's none until the first check finishes
+#[derive(Serialize)]
+struct ResponseHealth {
+    checking: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<HealthReport>,
 }
 
 #[derive(Serialize, Clone)]
@@ -41,6 +205,31 @@ struct ResponseArtist {
     num_songs: usize,
 }
 
+//Artists order first
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum SuggestionKind {
+    #[serde(rename = "artist")]
+    Artist,
+    #[serde(rename = "song")]
+    Song,
+}
+
+//A search result shown while the query is being typed. Highlights are byte
+//ranges of the name, and of the artist name of a song, matching the query.
+#[derive(Serialize, Debug)]
+struct ResponseSuggestion {
+    kind: SuggestionKind,
+    id: u64,
+    name: String,
+    highlights: Vec<(usize, usize)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    artist_highlights: Vec<(usize, usize)>,
+}
+
 #[derive(Serialize)]
 struct Response {
     status: &'static str,
@@ -73,11 +262,33 @@ enum DataType {
     #[serde(rename = "artists")]
     Artist(Vec<ResponseArtist>),
     #[serde(rename = "queue")]
-    Queue(Vec<ResponseSong>),
+    Queue(Vec<ResponseQueueEntry>),
+    #[serde(rename = "now_playing")]
+    NowPlaying(ResponseNowPlaying),
     #[serde(rename = "next_song")]
-    NextSong { mp3: String, cdg: String },
+    NextSong {
+        entry_id: u64,
+        audio: String,
+        cdg: String,
+        tempo: u8,
+    },
     #[serde(rename = "config")]
     Config(Config),
+    #[serde(rename = "overrides")]
+    Overrides(Overrides),
+    #[serde(rename = "health")]
+    Health(ResponseHealth),
+    #[serde(rename = "suggestions")]
+    Suggestions(Vec<ResponseSuggestion>),
+    #[serde(rename = "history")]
+    History(Vec<Play>),
+    #[serde(rename = "stats")]
+    Stats {
+        songs: Vec<PlayCount>,
+        artists: Vec<PlayCount>,
+    },
+    #[serde(rename = "sessions")]
+    Sessions(Vec<Session>),
 }
 
 #[derive(Deserialize)]
@@ -88,6 +299,52 @@ struct Params {
     sort_key: Option<SortKey>,
     sort_direction: Option<SortDirection>,
     favorites_only: Option<bool>,
+    library: Option<String>,
+    genre: Option<String>,
+    year: Option<i32>,
+    language: Option<String>,
+    album: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SuggestParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+//`refresh` starts a new health check, even if there's a report already
+#[derive(Deserialize)]
+struct HealthParams {
+    #[serde(default)]
+    refresh: bool,
+}
+
+//Times are milliseconds since the epoch, the span is every play when not given
+#[derive(Deserialize)]
+struct HistoryParams {
+    from: Option<u64>,
+    to: Option<u64>,
+    page: Option<u32>,
+    limit: Option<usize>,
+}
+
+impl HistoryParams {
+    fn plays(&self, history: &Mutex<History>) -> Result<Vec<Play>, Error> {
+        let from = self.from.unwrap_or(0);
+        let to = self.to.unwrap_or(u64::MAX);
+        history
+            .lock()
+            .unwrap()
+            .plays(from, to)
+            .map_err(|e| error::ErrorInternalServerError(e.to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    format: Option<String>,
+    favorites_only: Option<bool>,
+    library: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Copy)]
@@ -98,6 +355,12 @@ enum SortKey {
     Artist,
     #[serde(rename = "numsongs")]
     NumSongs,
+    #[serde(rename = "duration")]
+    Duration,
+    #[serde(rename = "year")]
+    Year,
+    #[serde(rename = "genre")]
+    Genre,
 }
 
 #[derive(Deserialize, Clone, Copy, PartialEq)]
@@ -108,60 +371,70 @@ enum SortDirection {
     Desc,
 }
 
+//Tag filters ignore case, songs without the tag never match
+fn matches_tag(filter: &Option<String>, tag: &Option<String>) -> bool {
+    match (filter, tag) {
+        (None, _) => true,
+        (Some(filter), Some(tag)) => filter.eq_ignore_ascii_case(tag),
+        (Some(_), None) => false,
+    }
+}
+
 fn api_songs(
-    collection: web::Data<Collection>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
     favorites: web::Data<Box<FavoritesDB>>,
     params: web::Query<Params>,
 ) -> Result<web::Json<Response>, Error> {
-    let songs = collection.get_ref().by_song.clone();
+    let collection = collection.read().unwrap();
     let favorites = favorites.data().unwrap_or_default();
 
-    let mut songs: Vec<ResponseSong> = songs
+    //With a query only the songs it matches are looked at, in order of relevance
+    let ranked = search(&collection.song_index, &params.query);
+    let candidates: Vec<&Kfile> = match &ranked {
+        Some(ranked) => ranked
+            .iter()
+            .filter_map(|(id, _)| collection.by_song.get(id))
+            .collect(),
+        None => collection.by_song.values().collect(),
+    };
+
+    let mut songs: Vec<&Kfile> = candidates
         .into_iter()
-        .map(|(id, song)| ResponseSong {
-            id,
-            name: song.song,
-            artist_id: song.artist_hash,
-            artist_name: song.artist,
-            favorite: favorites.contains(&id),
-        })
         .filter(|song| {
             if params.favorites_only.unwrap_or_default() {
-                song.favorite
+                favorites.contains(&song.id)
             } else {
                 true
             }
         })
+        .filter(|song| match &params.library {
+            Some(library) => song.library.as_ref() == Some(library),
+            None => true,
+        })
+        .filter(|song| match params.artist_id {
+            Some(artist_id) => song.artist_hash == artist_id,
+            None => true,
+        })
+        .filter(|song| matches_tag(&params.genre, &song.genre))
+        .filter(|song| matches_tag(&params.language, &song.language))
+        .filter(|song| matches_tag(&params.album, &song.album))
+        .filter(|song| params.year.is_none() || song.year == params.year)
         .collect();
 
-    let sort_key = params.sort_key.unwrap_or(SortKey::Song);
-    let sort_direction = params.sort_direction.unwrap_or(SortDirection::Asc);
-    songs.sort_by_key(|song| match sort_key {
-        SortKey::Artist => song.artist_name.to_lowercase(),
-        _ => song.name.to_lowercase(),
-    });
-    if sort_direction == SortDirection::Desc {
-        songs.reverse();
-    }
-
-    if let Some(artist_id) = params.artist_id {
-        songs = songs
-            .into_iter()
-            .filter(|song| song.artist_id == artist_id)
-            .collect();
-    }
-
-    if let Some(query) = &params.query {
-        songs = songs
-            .into_iter()
-            .filter(|song| {
-                song.name.to_lowercase().contains(&query.to_lowercase())
-                    || song
-                        .artist_name
-                        .to_lowercase()
-                        .contains(&query.to_lowercase())
-            })
-            .collect();
+    //Search results stay ranked unless a sort is asked for
+    if ranked.is_none() || params.sort_key.is_some() {
+        let sort_key = params.sort_key.unwrap_or(SortKey::Song);
+        let sort_direction = params.sort_direction.unwrap_or(SortDirection::Asc);
+        songs.sort_by_cached_key(|song| match sort_key {
+            SortKey::Artist => (None, song.artist.to_lowercase()),
+            SortKey::Duration => (song.duration.map(i64::from), String::new()),
+            SortKey::Year => (song.year.map(i64::from), String::new()),
+            SortKey::Genre => (None, song.genre.clone().unwrap_or_default().to_lowercase()),
+            _ => (None, song.song.to_lowercase()),
+        });
+        if sort_direction == SortDirection::Desc {
+            songs.reverse();
+        }
     }
 
     let page = params.page.unwrap_or(1);
@@ -179,11 +452,13 @@ fn api_songs(
         return Ok(web::Json(response));
     }
 
-    songs = songs
+    let songs: Vec<ResponseSong> = songs
         .chunks(PAGE_SIZE)
         .nth((page - 1) as usize)
         .unwrap_or(&[])
-        .to_vec();
+        .iter()
+        .map(|song| ResponseSong::new(song.id, (*song).clone(), favorites.contains(&song.id)))
+        .collect();
 
     let response = Response {
         status: "ok",
@@ -196,42 +471,50 @@ fn api_songs(
     Ok(web::Json(response))
 }
 
+//Ids matching a search query, best match first and ties in id order so pages
+//stay stable. None without a query.
+fn search(index: &SearchIndex, query: &Option<String>) -> Option<Vec<(u64, u32)>> {
+    let query = query.as_ref().filter(|query| !query.trim().is_empty())?;
+    let mut ranked = index.search(query);
+    ranked.sort_by(|(a_id, a_score), (b_id, b_score)| b_score.cmp(a_score).then(a_id.cmp(b_id)));
+    Some(ranked)
+}
+
 fn api_artists(
-    collection: web::Data<Collection>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
     params: web::Query<Params>,
 ) -> Result<web::Json<Response>, Error> {
-    let artists = collection.get_ref().by_artist.clone();
-    let mut artists: Vec<ResponseArtist> = artists
+    let collection = collection.read().unwrap();
+
+    let ranked = search(&collection.artist_index, &params.query);
+    let candidates: Vec<(&u64, &Artist)> = match &ranked {
+        Some(ranked) => ranked
+            .iter()
+            .filter_map(|(id, _)| collection.by_artist.get_key_value(id))
+            .collect(),
+        None => collection.by_artist.iter().collect(),
+    };
+
+    let mut artists: Vec<ResponseArtist> = candidates
         .into_iter()
+        .filter(|(id, _)| params.artist_id.is_none() || params.artist_id == Some(**id))
         .map(|(id, artist)| ResponseArtist {
-            id,
-            name: artist.name,
+            id: *id,
+            name: artist.name.clone(),
             num_songs: artist.num_songs,
         })
         .collect();
 
-    if let Some(artist_id) = params.artist_id {
-        artists = artists
-            .into_iter()
-            .filter(|artist| artist.id == artist_id)
-            .collect();
-    }
-
-    let sort_key = params.sort_key.unwrap_or(SortKey::Artist);
-    let sort_direction = params.sort_direction.unwrap_or(SortDirection::Asc);
-    artists.sort_by_key(|artist| match sort_key {
-        SortKey::NumSongs => format!("{:0>5}", artist.num_songs),
-        _ => artist.name.to_lowercase(),
-    });
-    if sort_direction == SortDirection::Desc {
-        artists.reverse();
-    }
-
-    if let Some(query) = &params.query {
-        artists = artists
-            .into_iter()
-            .filter(|artist| artist.name.to_lowercase().contains(&query.to_lowercase()))
-            .collect();
+    if ranked.is_none() || params.sort_key.is_some() {
+        let sort_key = params.sort_key.unwrap_or(SortKey::Artist);
+        let sort_direction = params.sort_direction.unwrap_or(SortDirection::Asc);
+        artists.sort_by_key(|artist| match sort_key {
+            SortKey::NumSongs => format!("{:0>5}", artist.num_songs),
+            _ => artist.name.to_lowercase(),
+        });
+        if sort_direction == SortDirection::Desc {
+            artists.reverse();
+        }
     }
 
     let page = params.page.unwrap_or(1);
@@ -266,24 +549,193 @@ fn api_artists(
     Ok(web::Json(response))
 }
 
+fn api_suggest(
+    collection: web::Data<Arc<RwLock<Collection>>>,
+    params: web::Query<SuggestParams>,
+) -> web::Json<Response> {
+    let limit = params.limit.unwrap_or(SUGGEST_SIZE).min(MAX_SUGGEST_SIZE);
+    let collection = collection.read().unwrap();
+    let suggestions = suggest(&collection, &params.q, limit);
+
+    web::Json(Response {
+        data: Some(DataType::Suggestions(suggestions)),
+        ..Response::default()
+    })
+}
+
+//Best artist and song matches for a query, mixed by score. An artist ties
+//ahead of its songs, as picking it narrows the search down.
+fn suggest(collection: &Collection, query: &str, limit: usize) -> Vec<ResponseSuggestion> {
+    let search_query = Some(query.to_owned());
+    let artists = search(&collection.artist_index, &search_query).unwrap_or_default();
+    let songs = search(&collection.song_index, &search_query).unwrap_or_default();
+
+    let mut ranked: Vec<(u32, SuggestionKind, u64)> = artists
+        .into_iter()
+        .take(limit)
+        .map(|(id, score)| (score, SuggestionKind::Artist, id))
+        .chain(
+            songs
+                .into_iter()
+                .take(limit)
+                .map(|(id, score)| (score, SuggestionKind::Song, id)),
+        )
+        .collect();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    ranked
+        .into_iter()
+        .filter_map(|(_, kind, id)| match kind {
+            SuggestionKind::Artist => {
+                let artist = collection.by_artist.get(&id)?;
+                Some(ResponseSuggestion {
+                    kind,
+                    id,
+                    highlights: highlights(&artist.name, query),
+                    name: artist.name.clone(),
+                    artist_id: None,
+                    artist_name: None,
+                    artist_highlights: vec![],
+                })
+            }
+            SuggestionKind::Song => {
+                let song = collection.by_song.get(&id)?;
+                Some(ResponseSuggestion {
+                    kind,
+                    id,
+                    highlights: highlights(&song.song, query),
+                    name: song.song.clone(),
+                    artist_id: Some(song.artist_hash),
+                    artist_highlights: highlights(&song.artist, query),
+                    artist_name: Some(song.artist.clone()),
+                })
+            }
+        })
+        .take(limit)
+        .collect()
+}
+
+fn api_export(
+    collection: web::Data<Arc<RwLock<Collection>>>,
+    favorites: web::Data<Box<FavoritesDB>>,
+    params: web::Query<ExportParams>,
+) -> Result<HttpResponse, Error> {
+    let format = match &params.format {
+        Some(format) => format
+            .parse::<ExportFormat>()
+            .map_err(|e| error::ErrorBadRequest(e.to_string()))?,
+        None => ExportFormat::Html,
+    };
+    let favorites = if params.favorites_only.unwrap_or_default() {
+        Some(favorites.data().unwrap_or_default())
+    } else {
+        None
+    };
+
+    let songbook = export::songbook(
+        &collection.read().unwrap(),
+        favorites.as_ref(),
+        params.library.as_deref(),
+    );
+    let body = export::render(&songbook, format)
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    //The html songbook opens to be printed, the rest download
+    let mut response = HttpResponse::Ok();
+    if format != ExportFormat::Html {
+        response.header(
+            "Content-Disposition",
+            format!("attachment; filename=\"songbook.{}\"", format.extension()),
+        );
+    }
+    Ok(response.content_type(format.content_type()).body(body))
+}
+
+//Newest first
+fn api_history(
+    history: web::Data<Arc<Mutex<History>>>,
+    params: web::Query<HistoryParams>,
+) -> Result<web::Json<Response>, Error> {
+    let mut plays = params.plays(&history)?;
+    plays.reverse();
+
+    let page = params.page.unwrap_or(1);
+    let pages = (plays.len() as f32 / PAGE_SIZE as f32).ceil() as u32;
+    if page == 0 || (page > pages && pages > 0) {
+        return Ok(web::Json(Response {
+            status: "error",
+            error_message: Some("Incorrect page number".to_string()),
+            ..Response::default()
+        }));
+    }
+
+    let plays = plays
+        .chunks(PAGE_SIZE)
+        .nth((page - 1) as usize)
+        .unwrap_or(&[])
+        .to_vec();
+
+    Ok(web::Json(Response {
+        status: "ok",
+        data: Some(DataType::History(plays)),
+        page: Some(page),
+        total_pages: Some(pages),
+        ..Response::default()
+    }))
+}
+
+fn api_stats(
+    history: web::Data<Arc<Mutex<History>>>,
+    params: web::Query<HistoryParams>,
+) -> Result<web::Json<Response>, Error> {
+    let plays = params.plays(&history)?;
+    let limit = params.limit.unwrap_or(STATS_SIZE).min(MAX_STATS_SIZE);
+
+    Ok(web::Json(Response {
+        status: "ok",
+        data: Some(DataType::Stats {
+            songs: history::top_songs(&plays, limit),
+            artists: history::top_artists(&plays, limit),
+        }),
+        ..Response::default()
+    }))
+}
+
+fn api_sessions(
+    history: web::Data<Arc<Mutex<History>>>,
+    params: web::Query<HistoryParams>,
+) -> Result<web::Json<Response>, Error> {
+    let plays = params.plays(&history)?;
+
+    Ok(web::Json(Response {
+        status: "ok",
+        data: Some(DataType::Sessions(history::sessions(&plays))),
+        ..Response::default()
+    }))
+}
+
 fn api_queue(
-    queue: web::Data<Arc<Mutex<Vec<Kfile>>>>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
     favorites: web::Data<Box<FavoritesDB>>,
 ) -> Result<web::Json<Response>, Error> {
-    let queue = queue.get_ref().as_ref().lock().unwrap().clone();
+    let playback = playback.lock().unwrap();
+    let queue = playback.queue();
     let favorites = favorites.data().unwrap_or_default();
 
-    let queue: Vec<ResponseSong> = queue
-        .into_iter()
-        .map(|kfile| {
-            let id = calculate_hash(&kfile);
-
-            ResponseSong {
-                id,
-                name: kfile.song,
-                artist_name: kfile.artist,
-                artist_id: kfile.artist_hash,
-                favorite: favorites.contains(&id),
+    let queue: Vec<ResponseQueueEntry> = queue
+        .entries()
+        .iter()
+        .zip(queue.start_times(playback.elapsed()))
+        .map(|(entry, start_in)| {
+            let id = entry.kfile.id;
+
+            ResponseQueueEntry {
+                entry_id: entry.id,
+                song: ResponseSong::new(id, entry.kfile.clone(), favorites.contains(&id)),
+                singer: entry.singer.clone(),
+                start_in,
+                key: entry.key,
+                tempo: entry.tempo,
             }
         })
         .collect();
@@ -299,15 +751,64 @@ fn api_queue(
     Ok(web::Json(response))
 }
 
+fn api_nowplaying(
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    favorites: web::Data<Box<FavoritesDB>>,
+) -> Result<web::Json<Response>, Error> {
+    let playback = playback.lock().unwrap();
+    let favorites = favorites.data().unwrap_or_default();
+    let entry = playback.playing();
+
+    let now_playing = ResponseNowPlaying {
+        state: playback.state(),
+        entry_id: entry.map(|entry| entry.id),
+        song: entry.map(|entry| {
+            let id = entry.kfile.id;
+            ResponseSong::new(id, entry.kfile.clone(), favorites.contains(&id))
+        }),
+        singer: entry.and_then(|entry| entry.singer.clone()),
+        elapsed: (playback.elapsed() / 1000) as u32,
+        duration: entry.and_then(|entry| entry.kfile.duration),
+        key: entry.map(|entry| entry.key).unwrap_or(0),
+        tempo: entry.map(|entry| entry.tempo).unwrap_or(100),
+    };
+
+    let response = Response {
+        status: "ok",
+        error_message: None,
+        data: Some(DataType::NowPlaying(now_playing)),
+        page: None,
+        total_pages: None,
+    };
+
+    Ok(web::Json(response))
+}
+
 fn api_add(
-    form: web::Form<Song>,
-    collection: web::Data<Collection>,
+    form: web::Form<AddSong>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
     worker_sender: web::Data<Sender<WorkerCommand>>,
 ) -> HttpResponse {
-    let hash = form.hash;
-    let kfile = collection.by_song[&hash].clone();
-    log::info!("Song added to queue: {} - {}", kfile.artist, kfile.song);
-    let cmd = WorkerCommand::AddQueue { kfile };
+    let form = form.into_inner();
+    let kfile = match collection.read().unwrap().by_song.get(&form.hash) {
+        Some(kfile) => kfile.clone(),
+        None => return song_not_found(),
+    };
+    let entry = QueueEntry::new(kfile, form.singer);
+    match &entry.singer {
+        Some(singer) => log::info!(
+            "Song added to queue: {} - {} ({})",
+            entry.kfile.artist,
+            entry.kfile.song,
+            singer
+        ),
+        None => log::info!(
+            "Song added to queue: {} - {}",
+            entry.kfile.artist,
+            entry.kfile.song
+        ),
+    }
+    let cmd = WorkerCommand::AddQueue { entry };
     worker_sender.send(cmd).unwrap();
     HttpResponse::Ok().json(Response {
         status: "ok",
@@ -317,11 +818,14 @@ fn api_add(
 
 fn api_playnow(
     form: web::Form<Song>,
-    collection: web::Data<Collection>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
     worker_sender: web::Data<Sender<WorkerCommand>>,
 ) -> HttpResponse {
     let hash = form.hash;
-    let kfile = collection.by_song[&hash].clone();
+    let kfile = match collection.read().unwrap().by_song.get(&hash) {
+        Some(kfile) => kfile.clone(),
+        None => return song_not_found(),
+    };
     log::info!("Play now requested for: {} - {}", kfile.artist, kfile.song);
     let cmd = WorkerCommand::PlayNow { kfile };
     worker_sender.send(cmd).unwrap();
@@ -331,6 +835,126 @@ fn api_playnow(
     })
 }
 
+//Queue edits name songs by entry id. One that's gone was most likely removed or
+//played since the queue was loaded, which is reported rather than guessed at.
+fn queue_edit(
+    ids: &[u64],
+    cmd: WorkerCommand,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let playback = playback.lock().unwrap();
+    if ids
+        .iter()
+        .any(|id| playback.queue().position(*id).is_none())
+    {
+        return HttpResponse::Ok().json(Response {
+            status: "error",
+            error_message: Some("Song is no longer in the queue".to_string()),
+            ..Response::default()
+        });
+    }
+    drop(playback);
+
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
+fn api_queue_remove(
+    form: web::Form<QueueRemove>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let id = form.id;
+    log::info!("Queue remove requested: {}", id);
+    let cmd = WorkerCommand::RemoveQueue { id };
+    queue_edit(&[id], cmd, playback, worker_sender)
+}
+
+fn api_queue_move(
+    form: web::Form<QueueMove>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let (id, position) = (form.id, form.position);
+    log::info!("Queue move requested: {} to {}", id, position);
+    let cmd = WorkerCommand::MoveQueue { id, position };
+    queue_edit(&[id], cmd, playback, worker_sender)
+}
+
+fn api_queue_swap(
+    form: web::Form<QueueSwap>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let (id, other) = (form.id, form.other);
+    log::info!("Queue swap requested: {} and {}", id, other);
+    let cmd = WorkerCommand::SwapQueue { id, other };
+    queue_edit(&[id, other], cmd, playback, worker_sender)
+}
+
+//The song playing can change key too, the native player shifts it as it plays.
+//The web player can only play faster or slower, so it's left as it is.
+fn api_queue_key(
+    form: web::Form<QueueKey>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    if CONFIG.use_web_player {
+        return HttpResponse::Ok().json(Response {
+            status: "error",
+            error_message: Some("The web player can't change key".to_string()),
+            ..Response::default()
+        });
+    }
+    let (id, key) = (form.id, form.key);
+    log::info!("Key change requested: {} to {:+}", id, key);
+    let cmd = WorkerCommand::SetKey { id, key };
+    entry_edit(id, cmd, playback, worker_sender)
+}
+
+//Both players follow a tempo change to the song playing
+fn api_queue_tempo(
+    form: web::Form<QueueTempo>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let (id, tempo) = (form.id, form.tempo);
+    log::info!("Tempo change requested: {} to {}%", id, tempo);
+    let cmd = WorkerCommand::SetTempo { id, tempo };
+    entry_edit(id, cmd, playback, worker_sender)
+}
+
+//Edits a queued song like `queue_edit`, or the one playing
+fn entry_edit(
+    id: u64,
+    cmd: WorkerCommand,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let playing = playback.lock().unwrap().playing().map(|entry| entry.id) == Some(id);
+    if !playing {
+        return queue_edit(&[id], cmd, playback, worker_sender);
+    }
+
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
+fn song_not_found() -> HttpResponse {
+    HttpResponse::Ok().json(Response {
+        status: "error",
+        error_message: Some("Song not found".to_string()),
+        ..Response::default()
+    })
+}
+
 fn api_next(worker_sender: web::Data<Sender<WorkerCommand>>) -> HttpResponse {
     let cmd = WorkerCommand::Next;
     log::info!("Next song requested");
@@ -361,6 +985,50 @@ fn api_stop(worker_sender: web::Data<Sender<WorkerCommand>>) -> HttpResponse {
     })
 }
 
+fn api_pause(worker_sender: web::Data<Sender<WorkerCommand>>) -> HttpResponse {
+    let cmd = WorkerCommand::Pause;
+    log::info!("Pause requested");
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
+fn api_resume(worker_sender: web::Data<Sender<WorkerCommand>>) -> HttpResponse {
+    let cmd = WorkerCommand::Resume;
+    log::info!("Resume requested");
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
+fn api_seek(
+    form: web::Form<Seek>,
+    worker_sender: web::Data<Sender<WorkerCommand>>,
+) -> HttpResponse {
+    let secs = form.seconds;
+    log::info!("Seek requested: {}s", secs);
+    let cmd = WorkerCommand::Seek { secs };
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
+fn api_restart(worker_sender: web::Data<Sender<WorkerCommand>>) -> HttpResponse {
+    let cmd = WorkerCommand::Restart;
+    log::info!("Restart requested");
+    worker_sender.send(cmd).unwrap();
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        ..Response::default()
+    })
+}
+
 fn api_config() -> HttpResponse {
     let config = CONFIG.clone();
 
@@ -419,54 +1087,298 @@ fn api_remove_favorite(
     })
 }
 
-fn api_player_next(queue: web::Data<Arc<Mutex<Vec<Kfile>>>>) -> HttpResponse {
-    let _queue = queue.lock().unwrap();
-    if _queue.len() == 0 {
-        drop(_queue);
+//Checking decodes every song, which can take minutes, so it's done on its own
+//thread. The first request starts it, later ones get the last report and
+//start a new check with `refresh`.
+fn api_admin_health(
+    params: web::Query<HealthParams>,
+    health: web::Data<Arc<Mutex<HealthCheck>>>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
+) -> HttpResponse {
+    let mut check = health.lock().unwrap();
+    if (params.refresh || check.report().is_none()) && check.start() {
+        let health = health.get_ref().clone();
+        let collection = collection.get_ref().clone();
+        std::thread::spawn(move || {
+            let collection = collection.read().unwrap().clone();
+            let report = health::check(&CONFIG.libraries(), &collection);
+            log::info!("Health report issues found: {}", report.num_issues());
+            health.lock().unwrap().finish(report);
+        });
+    }
+
+    let health = ResponseHealth {
+        checking: check.checking(),
+        report: check.report().cloned(),
+    };
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        data: Some(DataType::Health(health)),
+        ..Response::default()
+    })
+}
+
+fn api_admin_overrides(overrides_db: web::Data<Box<OverridesDB>>) -> HttpResponse {
+    let overrides = overrides_db
+        .load()
+        .and_then(|_| overrides_db.get_data(false));
+
+    match overrides {
+        Ok(overrides) => HttpResponse::Ok().json(Response {
+            status: "ok",
+            data: Some(DataType::Overrides(overrides)),
+            ..Response::default()
+        }),
+        Err(e) => HttpResponse::Ok().json(Response {
+            status: "error",
+            error_message: Some(e.to_string()),
+            ..Response::default()
+        }),
+    }
+}
+
+//Blank fields clear that part of the override
+fn api_admin_edit_song(
+    form: web::Form<SongEdit>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
+    collection_db: web::Data<Box<CollectionDB>>,
+    overrides_db: web::Data<Box<OverridesDB>>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    match collection_db.get(form.hash) {
+        Ok(Some(_)) => {}
+        Ok(None) => return song_not_found(),
+        Err(e) => {
+            return HttpResponse::Ok().json(Response {
+                status: "error",
+                error_message: Some(e.to_string()),
+                ..Response::default()
+            })
+        }
+    }
+    let song_override = SongOverride {
+        artist: form.artist.filter(|artist| !artist.trim().is_empty()),
+        title: form.title.filter(|title| !title.trim().is_empty()),
+    };
+
+    if let Err(e) = set_song_override(&*overrides_db, form.hash, song_override) {
         return HttpResponse::Ok().json(Response {
             status: "error",
-            error_message: Some("no songs in queue".to_string()),
+            error_message: Some(e.to_string()),
             ..Response::default()
         });
     }
 
-    let mp3 = _queue[0]
-        .mp3_path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let cdg = _queue[0]
-        .cdg_path
-        .file_name()
+    log::info!("Song metadata override updated: {}", form.hash);
+
+    reload_collection(&collection, &collection_db, &overrides_db)
+}
+
+//Lists an artist's songs under another name, merging them with that artist if
+//it exists. A blank name undoes it.
+fn api_admin_edit_artist(
+    form: web::Form<ArtistEdit>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
+    collection_db: web::Data<Box<CollectionDB>>,
+    overrides_db: web::Data<Box<OverridesDB>>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let name = form.name.filter(|name| !name.trim().is_empty());
+
+    //A misspelled artist would be saved, but never match a song
+    if name.is_some() {
+        match artist_credited(&collection_db, &overrides_db, &form.artist) {
+            Ok(true) => {}
+            Ok(false) => {
+                return HttpResponse::Ok().json(Response {
+                    status: "error",
+                    error_message: Some("Artist not found".to_string()),
+                    ..Response::default()
+                })
+            }
+            Err(e) => {
+                return HttpResponse::Ok().json(Response {
+                    status: "error",
+                    error_message: Some(e.to_string()),
+                    ..Response::default()
+                })
+            }
+        }
+    }
+
+    if let Err(e) = set_artist_override(&*overrides_db, form.artist.clone(), name.clone()) {
+        return HttpResponse::Ok().json(Response {
+            status: "error",
+            error_message: Some(e.to_string()),
+            ..Response::default()
+        });
+    }
+
+    match name {
+        Some(name) => log::info!("Artist override updated: {} -> {}", form.artist, name),
+        None => log::info!("Artist override removed: {}", form.artist),
+    }
+
+    reload_collection(&collection, &collection_db, &overrides_db)
+}
+
+//Rebuild the collection from the db so edited overrides take effect
+fn reload_collection(
+    collection: &RwLock<Collection>,
+    collection_db: &CollectionDB,
+    overrides_db: &OverridesDB,
+) -> HttpResponse {
+    match load_collection(collection_db, overrides_db) {
+        Ok(reloaded) => {
+            *collection.write().unwrap() = reloaded;
+            HttpResponse::Ok().json(Response {
+                status: "ok",
+                ..Response::default()
+            })
+        }
+        Err(e) => HttpResponse::Ok().json(Response {
+            status: "error",
+            error_message: Some(e.to_string()),
+            ..Response::default()
+        }),
+    }
+}
+
+//The browser starts the song as soon as it has it
+fn api_player_next(playback: web::Data<Arc<Mutex<Playback>>>) -> HttpResponse {
+    let mut playback = playback.lock().unwrap();
+    let entry = match playback.next_song() {
+        Some(entry) => {
+            playback.started();
+            entry
+        }
+        None => {
+            return HttpResponse::Ok().json(Response {
+                status: "error",
+                error_message: Some("no songs in queue".to_string()),
+                ..Response::default()
+            })
+        }
+    };
+
+    drop(playback);
+
+    let kfile = &entry.kfile;
+    let audio = song_file_name(kfile, &kfile.audio_path);
+    let cdg = song_file_name(kfile, &kfile.cdg_path);
+    let tempo = entry.tempo;
+
+    HttpResponse::Ok().json(Response {
+        status: "ok",
+        data: Some(DataType::NextSong {
+            entry_id: entry.id,
+            audio,
+            cdg,
+            tempo,
+        }),
+        ..Response::default()
+    })
+}
+
+//Name the web player should request from /songs/. Songs inside an archive or
+//outside song_path (in a named library) are prefixed with their id, so they get
+//served by serve_song_file.
+fn song_file_name(kfile: &Kfile, path: &Path) -> String {
+    let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+    if kfile.zip_path.is_some() || kfile.library.is_some() {
+        format!("{}/{}", kfile.id, file_name)
+    } else {
+        file_name
+    }
+}
+
+//Songs can be large or inside an archive, so they're read on the blocking
+//thread pool rather than the worker
+fn serve_song_file(
+    path: web::Path<(u64, String)>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let (hash, file_name) = path.into_inner();
+    let kfile = collection
+        .read()
         .unwrap()
-        .to_str()
+        .by_song
+        .get(&hash)
+        .cloned()
+        .ok_or_else(|| error::ErrorNotFound("Song not found"));
+
+    future::result(kfile).and_then(move |kfile| {
+        web::block(move || read_song_file(&kfile, &file_name))
+            .map_err(|e| match e {
+                BlockingError::Error(e) => error::ErrorInternalServerError(e.to_string()),
+                BlockingError::Canceled => error::ErrorInternalServerError("Read canceled"),
+            })
+            .and_then(|data| match data {
+                Some(data) => Ok(HttpResponse::Ok()
+                    .content_type("application/octet-stream")
+                    .body(data)),
+                None => Err(error::ErrorNotFound("File not found")),
+            })
+    })
+}
+
+//The song's audio or cdg file named `file_name`, None if it's neither
+fn read_song_file(kfile: &Kfile, file_name: &str) -> Result<Option<Vec<u8>>, failure::Error> {
+    let mut file = if kfile.audio_path.file_name() == Some(file_name.as_ref()) {
+        kfile.open_audio()?
+    } else if kfile.cdg_path.file_name() == Some(file_name.as_ref()) {
+        kfile.open_cdg()?
+    } else {
+        return Ok(None);
+    };
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+//Rendering a preview the first time plays up to a minute of cdg, so it's done
+//on the blocking thread pool rather than the worker
+fn serve_song_preview(
+    path: web::Path<u64>,
+    collection: web::Data<Arc<RwLock<Collection>>>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let kfile = collection
+        .read()
         .unwrap()
-        .to_string();
-    drop(_queue);
+        .by_song
+        .get(&path.into_inner())
+        .cloned()
+        .ok_or_else(|| error::ErrorNotFound("Song not found"));
+
+    future::result(kfile).and_then(|kfile| {
+        web::block(move || thumbnail::preview(&kfile, &CONFIG.data_path.join(PREVIEW_DIR)))
+            .map(|png| HttpResponse::Ok().content_type("image/png").body(png))
+            .map_err(|e| match e {
+                BlockingError::Error(e) => error::ErrorNotFound(e.to_string()),
+                BlockingError::Canceled => error::ErrorInternalServerError("Preview canceled"),
+            })
+    })
+}
+
+fn api_player_position(
+    form: web::Form<Position>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+) -> HttpResponse {
+    playback.lock().unwrap().progress(form.id, form.elapsed);
 
     HttpResponse::Ok().json(Response {
         status: "ok",
-        data: Some(DataType::NextSong { mp3, cdg }),
         ..Response::default()
     })
 }
 
-fn api_player_ended(queue: web::Data<Arc<Mutex<Vec<Kfile>>>>) -> HttpResponse {
+fn api_player_ended(
+    form: web::Form<Ended>,
+    playback: web::Data<Arc<Mutex<Playback>>>,
+) -> HttpResponse {
     log::info!("Web player has finished song");
-
-    let mut _queue = queue.lock().unwrap();
-    if _queue.len() == 0 {
-        drop(_queue);
-        return HttpResponse::Ok().json(Response {
-            status: "ok",
-            ..Response::default()
-        });
-    }
-
-    _queue.remove(0);
-    drop(_queue);
+    playback.lock().unwrap().ended(form.id, None);
 
     HttpResponse::Ok().json(Response {
         status: "ok",
@@ -494,7 +1406,8 @@ pub fn run() -> std::io::Result<()> {
     let server = HttpServer::new(|| {
         let collection = COLLECTION.clone();
         let worker_sender = WORKER_CHANNEL.0.clone();
-        let play_queue = PLAY_QUEUE.clone();
+        let playback = PLAYBACK.clone();
+        let history = HISTORY.clone();
 
         let mut static_path = CONFIG.data_path.clone();
         static_path.push("static");
@@ -503,28 +1416,70 @@ pub fn run() -> std::io::Result<()> {
 
         let favorites_db =
             FavoritesDB::initialize(&CONFIG.data_path).expect("Couldn't create favorites db");
+        let collection_db =
+            CollectionDB::initialize(&CONFIG.data_path).expect("Couldn't create collection db");
+        let overrides_db =
+            OverridesDB::initialize(&CONFIG.data_path).expect("Couldn't create overrides db");
 
         App::new()
             .data(collection)
             .data(worker_sender)
-            .data(play_queue)
+            .data(playback)
+            .data(history)
+            .data(HEALTH.clone())
             .data(favorites_db)
+            .data(collection_db)
+            .data(overrides_db)
             .wrap(middleware::Logger::default()) // enable logger
             .service(web::resource("/api/add").route(web::post().to(api_add)))
             .service(web::resource("/api/playnow").route(web::post().to(api_playnow)))
             .service(web::resource("/api/next").route(web::post().to(api_next)))
             .service(web::resource("/api/clear").route(web::post().to(api_clear)))
             .service(web::resource("/api/stop").route(web::post().to(api_stop)))
+            .service(web::resource("/api/pause").route(web::post().to(api_pause)))
+            .service(web::resource("/api/resume").route(web::post().to(api_resume)))
+            .service(web::resource("/api/seek").route(web::post().to(api_seek)))
+            .service(web::resource("/api/restart").route(web::post().to(api_restart)))
             .service(web::resource("/api/songs").route(web::get().to(api_songs)))
             .service(web::resource("/api/artists").route(web::get().to(api_artists)))
+            .service(web::resource("/api/suggest").route(web::get().to(api_suggest)))
+            .service(web::resource("/api/export").route(web::get().to(api_export)))
+            .service(
+                web::resource("/api/songs/{hash}/preview.png")
+                    .route(web::get().to_async(serve_song_preview)),
+            )
             .service(web::resource("/api/queue").route(web::get().to(api_queue)))
+            .service(web::resource("/api/nowplaying").route(web::get().to(api_nowplaying)))
+            .service(web::resource("/api/queue/remove").route(web::post().to(api_queue_remove)))
+            .service(web::resource("/api/queue/move").route(web::post().to(api_queue_move)))
+            .service(web::resource("/api/queue/swap").route(web::post().to(api_queue_swap)))
+            .service(web::resource("/api/queue/key").route(web::post().to(api_queue_key)))
+            .service(web::resource("/api/queue/tempo").route(web::post().to(api_queue_tempo)))
+            .service(web::resource("/api/history").route(web::get().to(api_history)))
+            .service(web::resource("/api/stats").route(web::get().to(api_stats)))
+            .service(web::resource("/api/sessions").route(web::get().to(api_sessions)))
             .service(web::resource("/api/config").route(web::get().to(api_config)))
             .service(web::resource("/api/player/next").route(web::get().to(api_player_next)))
             .service(web::resource("/api/player/ended").route(web::post().to(api_player_ended)))
+            .service(
+                web::resource("/api/player/position").route(web::post().to(api_player_position)),
+            )
             .service(web::resource("/api/favorites/add").route(web::post().to(api_add_favorite)))
             .service(
                 web::resource("/api/favorites/remove").route(web::post().to(api_remove_favorite)),
             )
+            .service(web::resource("/api/admin/health").route(web::get().to(api_admin_health)))
+            .service(
+                web::resource("/api/admin/overrides").route(web::get().to(api_admin_overrides)),
+            )
+            .service(web::resource("/api/admin/song").route(web::post().to(api_admin_edit_song)))
+            .service(
+                web::resource("/api/admin/artist").route(web::post().to(api_admin_edit_artist)),
+            )
+            .service(
+                web::resource("/songs/{hash}/{file_name}")
+                    .route(web::get().to_async(serve_song_file)),
+            )
             .service(actix_files::Files::new("/songs/", song_path))
             .service(actix_files::Files::new("/", static_path).index_file("index.html"))
             .default_service(
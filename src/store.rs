@@ -0,0 +1,377 @@
+use karaoke::collection::Kfile;
+use lazy_static::lazy_static;
+use rustbreak::{deser::Yaml, FileDatabase};
+use serde::{de::DeserializeOwned, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Transactional, Tree,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::rename,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+lazy_static! {
+    //sled locks its files, so every db on a data dir shares one open handle
+    static ref STORES: Mutex<HashMap<PathBuf, sled::Db>> = Mutex::new(HashMap::new());
+}
+
+//Directory of the store inside the data dir
+const STORE_DIR: &str = "store";
+
+fn open(data_path: &Path) -> Result<sled::Db, failure::Error> {
+    let path = data_path.join(STORE_DIR);
+    let mut stores = STORES.lock().unwrap();
+    if let Some(db) = stores.get(&path) {
+        return Ok(db.clone());
+    }
+    let db = sled::open(&path)?;
+    stores.insert(path, db.clone());
+    Ok(db)
+}
+
+fn transaction_error(e: TransactionError<failure::Error>) -> failure::Error {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => e.into(),
+    }
+}
+
+/// Songs keyed by id, stored as json, with an index on the credited artist.
+/// Every write goes through a transaction that keeps the index in step with
+/// the songs. Listings are sorted from the collection in memory and titles are
+/// looked up through the search index, so there's no title index.
+pub struct CollectionDB {
+    db: sled::Db,
+    songs: Tree,
+    by_artist: Tree,
+}
+
+impl CollectionDB {
+    pub fn open(data_path: &Path) -> Result<CollectionDB, failure::Error> {
+        let db = open(data_path)?;
+        let collection_db = CollectionDB {
+            songs: db.open_tree("songs")?,
+            by_artist: db.open_tree("songs_by_artist")?,
+            db,
+        };
+        collection_db.migrate_yaml(&data_path.join("db.yaml"))?;
+
+        Ok(collection_db)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Kfile>, failure::Error> {
+        match self.songs.get(id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn songs(&self) -> Result<Vec<Kfile>, failure::Error> {
+        self.songs
+            .iter()
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    /// Songs whose credited artist hashes to `artist_hash`
+    pub fn songs_by_artist(&self, artist_hash: u64) -> Result<Vec<Kfile>, failure::Error> {
+        self.indexed(&self.by_artist, &artist_hash.to_be_bytes())
+    }
+
+    fn indexed(&self, index: &Tree, prefix: &[u8]) -> Result<Vec<Kfile>, failure::Error> {
+        let mut songs = Vec::new();
+        for key in index.scan_prefix(prefix).keys() {
+            if let Some(kfile) = self.get(index_id(&key?))? {
+                songs.push(kfile);
+            }
+        }
+        Ok(songs)
+    }
+
+    /// Removes the songs with the given ids, then inserts the new songs, all at
+    /// once. A song already saved under the same id is replaced.
+    pub fn update(&self, removed: &[u64], inserted: &[Kfile]) -> Result<(), failure::Error> {
+        if removed.is_empty() && inserted.is_empty() {
+            return Ok(());
+        }
+        let values = inserted
+            .iter()
+            .map(serde_json::to_vec)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        (&self.songs, &self.by_artist)
+            .transaction(|(songs, by_artist)| {
+                let ids = removed.iter().chain(inserted.iter().map(|kfile| &kfile.id));
+                for id in ids {
+                    if let Some(old) = songs.remove(&id.to_be_bytes())? {
+                        let old: Kfile = serde_json::from_slice(&old)
+                            .map_err(|e| ConflictableTransactionError::Abort(e.into()))?;
+                        by_artist.remove(artist_key(&old))?;
+                    }
+                }
+                for (kfile, value) in inserted.iter().zip(values.iter()) {
+                    songs.insert(&kfile.id.to_be_bytes(), value.as_slice())?;
+                    by_artist.insert(artist_key(kfile), &[])?;
+                }
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    //Songs from the old yaml db are imported once, then it's kept as a backup
+    fn migrate_yaml(&self, yaml_path: &Path) -> Result<(), failure::Error> {
+        if !yaml_path.is_file() {
+            return Ok(());
+        }
+        let yaml = FileDatabase::<HashMap<u64, Kfile>, Yaml>::from_path(yaml_path, HashMap::new())?;
+        yaml.load()?;
+        let songs: Vec<Kfile> = yaml
+            .get_data(false)?
+            .into_iter()
+            .map(|(id, kfile)| Kfile { id, ..kfile })
+            .collect();
+        self.update(&[], &songs)?;
+        rename(yaml_path, yaml_path.with_extension("yaml.bak"))?;
+        log::info!("Songs migrated from {:?}: {}", yaml_path, songs.len());
+
+        Ok(())
+    }
+}
+
+/// Ids of favorite songs, one key each, so toggling a favorite only writes
+/// that song
+pub struct FavoritesDB {
+    db: sled::Db,
+    favorites: Tree,
+}
+
+impl FavoritesDB {
+    pub fn open(data_path: &Path) -> Result<FavoritesDB, failure::Error> {
+        let db = open(data_path)?;
+        let favorites_db = FavoritesDB {
+            favorites: db.open_tree("favorites")?,
+            db,
+        };
+        favorites_db.migrate_yaml(&data_path.join("favorites.yaml"))?;
+
+        Ok(favorites_db)
+    }
+
+    pub fn favorites(&self) -> Result<HashSet<u64>, failure::Error> {
+        self.favorites
+            .iter()
+            .keys()
+            .map(|key| Ok(index_id(&key?)))
+            .collect()
+    }
+
+    pub fn insert(&self, id: u64) -> Result<(), failure::Error> {
+        self.favorites.insert(id.to_be_bytes(), &[])?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, id: u64) -> Result<(), failure::Error> {
+        self.favorites.remove(id.to_be_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Moves favorites to new ids, all at once
+    pub fn remap(&self, remapped: &HashMap<u64, u64>) -> Result<usize, failure::Error> {
+        self.favorites
+            .transaction(|favorites| {
+                let mut count = 0;
+                for (old_id, new_id) in remapped {
+                    if favorites.remove(&old_id.to_be_bytes())?.is_some() {
+                        favorites.insert(&new_id.to_be_bytes(), &[])?;
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            })
+            .map_err(transaction_error)
+    }
+
+    fn migrate_yaml(&self, yaml_path: &Path) -> Result<(), failure::Error> {
+        if !yaml_path.is_file() {
+            return Ok(());
+        }
+        let yaml = FileDatabase::<HashSet<u64>, Yaml>::from_path(yaml_path, HashSet::new())?;
+        yaml.load()?;
+        let favorites = yaml.get_data(false)?;
+        for id in favorites.iter() {
+            self.favorites.insert(id.to_be_bytes(), &[])?;
+        }
+        self.db.flush()?;
+        rename(yaml_path, yaml_path.with_extension("yaml.bak"))?;
+        log::info!(
+            "Favorites migrated from {:?}: {}",
+            yaml_path,
+            favorites.len()
+        );
+
+        Ok(())
+    }
+}
+
+/// The play queue, written whole on every change so a restart can pick it
+/// back up
+#[derive(Debug)]
+pub struct QueueDB {
+    db: sled::Db,
+    queue: Tree,
+}
+
+//Key of the saved queue in its tree
+const QUEUE_KEY: &[u8] = b"queue";
+
+impl QueueDB {
+    pub fn open(data_path: &Path) -> Result<QueueDB, failure::Error> {
+        let db = open(data_path)?;
+        Ok(QueueDB {
+            queue: db.open_tree("queue")?,
+            db,
+        })
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> Result<Option<T>, failure::Error> {
+        match self.queue.get(QUEUE_KEY)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save<T: Serialize>(&self, queue: &T) -> Result<(), failure::Error> {
+        self.queue.insert(QUEUE_KEY, serde_json::to_vec(queue)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+/// Songs played, keyed by the time they started in milliseconds, so plays come
+/// back in order and a time span is a range of keys
+#[derive(Debug)]
+pub struct HistoryDB {
+    db: sled::Db,
+    history: Tree,
+}
+
+impl HistoryDB {
+    pub fn open(data_path: &Path) -> Result<HistoryDB, failure::Error> {
+        let db = open(data_path)?;
+        Ok(HistoryDB {
+            history: db.open_tree("history")?,
+            db,
+        })
+    }
+
+    /// Writes the play started at `key`, replacing what was written for it
+    pub fn insert<T: Serialize>(&self, key: u64, play: &T) -> Result<(), failure::Error> {
+        self.history
+            .insert(key.to_be_bytes(), serde_json::to_vec(play)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Plays started from `from` up to, not including, `to`, oldest first
+    pub fn range<T: DeserializeOwned>(&self, from: u64, to: u64) -> Result<Vec<T>, failure::Error> {
+        self.history
+            .range(from.to_be_bytes()..to.to_be_bytes())
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+}
+
+//Index keys end with the id of the song they point to
+fn index_id(key: &[u8]) -> u64 {
+    let mut id = [0; 8];
+    id.copy_from_slice(&key[key.len() - 8..]);
+    u64::from_be_bytes(id)
+}
+
+fn artist_key(kfile: &Kfile) -> Vec<u8> {
+    let mut key = kfile.artist_hash.to_be_bytes().to_vec();
+    key.extend_from_slice(&kfile.id.to_be_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::{
+        collection::artist_id,
+        test_util::{song, TestDir},
+    };
+
+    #[test]
+    fn test_update_indexes() {
+        let dir = TestDir::new("indexes");
+        let data_path = dir.path();
+
+        let db = CollectionDB::open(data_path).unwrap();
+        db.update(
+            &[],
+            &[
+                song(1, "The Testers", "Testing 123"),
+                song(2, "The Testers", "Another Test"),
+                song(3, "Other", "Testing 456"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            db.songs_by_artist(artist_id("The Testers")).unwrap().len(),
+            2
+        );
+
+        //Replacing a song moves its index entries, removing it drops them
+        db.update(&[2], &[song(1, "Other", "Testing 123")]).unwrap();
+        assert!(db
+            .songs_by_artist(artist_id("The Testers"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.songs_by_artist(artist_id("Other")).unwrap().len(), 2);
+        assert_eq!(db.songs().unwrap().len(), 2);
+
+        let favorites_db = FavoritesDB::open(data_path).unwrap();
+        favorites_db.insert(1).unwrap();
+        favorites_db.insert(3).unwrap();
+        favorites_db.remove(3).unwrap();
+        let remapped: HashMap<u64, u64> = vec![(1, 10)].into_iter().collect();
+        assert_eq!(favorites_db.remap(&remapped).unwrap(), 1);
+        let favorites = favorites_db.favorites().unwrap();
+        assert_eq!(favorites.len(), 1);
+        assert!(favorites.contains(&10));
+    }
+
+    #[test]
+    fn test_migrate_yaml() {
+        let dir = TestDir::new("yaml_db");
+        let data_path = dir.path();
+        std::fs::write(
+            data_path.join("db.yaml"),
+            "---\n1234:\n  mp3_path: songs/match.mp3\n  cdg_path: songs/match.cdg\n  artist: The Testers\n  artist_hash: 1\n  song: Testing 123\n",
+        )
+        .unwrap();
+        std::fs::write(data_path.join("favorites.yaml"), "---\n- 1234\n").unwrap();
+
+        let db = CollectionDB::open(data_path).unwrap();
+        let favorites_db = FavoritesDB::open(data_path).unwrap();
+        let kfile = db.get(1234).unwrap().unwrap();
+        assert_eq!(kfile.id, 1234);
+        assert_eq!(kfile.song, "Testing 123");
+        assert_eq!(db.songs_by_artist(1).unwrap().len(), 1);
+        assert!(favorites_db.favorites().unwrap().contains(&1234));
+        assert!(!data_path.join("db.yaml").exists());
+        assert!(data_path.join("db.yaml.bak").exists());
+        assert!(data_path.join("favorites.yaml.bak").exists());
+    }
+}
@@ -0,0 +1,78 @@
+//Fixtures shared by the tests
+use karaoke::{
+    collection::{artist_id, Database, Kfile},
+    config::{Config, Library},
+    queue::QueueEntry,
+    store::CollectionDB,
+};
+use std::{
+    fs::{create_dir_all, remove_dir_all},
+    path::PathBuf,
+};
+
+/// Songs in the test libraries
+pub const SONG_PATH: &str = "tests/test_data/songs";
+
+/// Length given to every test song, in seconds
+pub const SONG_SECS: u32 = 200;
+
+/// A data dir of a test's own under tests/test_data. It's removed when dropped,
+/// so a failed assert doesn't leave it in the way of the next run, and cleared
+/// first in case a run was killed before it could be.
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let path = PathBuf::from("tests/test_data").join(name);
+        let _ = remove_dir_all(&path);
+        create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
+
+/// The test songs as a single library
+pub fn libraries() -> Vec<Library> {
+    Config {
+        song_path: PathBuf::from(SONG_PATH),
+        ..Config::default()
+    }
+    .libraries()
+}
+
+/// A collection db in `dir` with the test songs scanned into it
+pub fn scanned_db(dir: &TestDir) -> CollectionDB {
+    let db = CollectionDB::initialize(dir.path()).unwrap();
+    db.refresh(&libraries()).unwrap();
+    *db
+}
+
+/// A song with no files, only what's shown of it
+pub fn song(id: u64, artist: &str, title: &str) -> Kfile {
+    Kfile {
+        id,
+        artist: artist.to_owned(),
+        artist_hash: artist_id(artist),
+        song: title.to_owned(),
+        duration: Some(SONG_SECS),
+        ..Kfile::default()
+    }
+}
+
+/// A song as queued, with the entry id the same as the song's
+pub fn queued(kfile: Kfile, singer: Option<&str>) -> QueueEntry {
+    let mut entry = QueueEntry::new(kfile, singer.map(str::to_owned));
+    entry.id = entry.kfile.id;
+    entry
+}
@@ -0,0 +1,167 @@
+use failure::{bail, Error};
+use image::{DynamicImage, GenericImage, ImageOutputFormat, RgbaImage};
+use karaoke::collection::Kfile;
+use std::{
+    fs::{self, metadata},
+    path::Path,
+    process,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+
+//Directory of cached previews inside the data dir
+pub const PREVIEW_DIR: &str = "previews";
+
+//Cdg runs at 75 sectors per second. The frame is checked every 0.2 seconds for
+//the first minute, title screens are drawn well within that.
+const SAMPLE_SECTORS: usize = 15;
+const MAX_SECTORS: usize = 75 * 60;
+
+//Numbers the temp files previews are written to
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+const CDG_WIDTH: u32 = 300;
+const CDG_HEIGHT: u32 = 216;
+
+/// PNG of the song's title screen, rendered once and then served from
+/// `cache_dir` until the song file changes
+pub fn preview(kfile: &Kfile, cache_dir: &Path) -> Result<Vec<u8>, Error> {
+    let cache_path = cache_dir.join(format!("{}.png", kfile.id));
+    if is_fresh(&cache_path, kfile.source_path()) {
+        return Ok(fs::read(&cache_path)?);
+    }
+
+    let frame = title_frame(kfile)?;
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(frame).write_to(&mut png, ImageOutputFormat::PNG)?;
+
+    //Written to a file of its own then renamed into place, so a preview being
+    //rendered for two requests at once is never read half written
+    fs::create_dir_all(cache_dir)?;
+    let temp_path = cache_dir.join(format!(
+        "{}.png.{}-{}.tmp",
+        kfile.id,
+        process::id(),
+        TEMP_COUNT.fetch_add(1, Relaxed)
+    ));
+    fs::write(&temp_path, &png)?;
+    fs::rename(&temp_path, &cache_path)?;
+
+    Ok(png)
+}
+
+fn is_fresh(cache_path: &Path, source_path: &Path) -> bool {
+    let modified = |path: &Path| metadata(path).and_then(|meta| meta.modified()).ok();
+    match (modified(cache_path), modified(source_path)) {
+        (Some(cached), Some(source)) => cached >= source,
+        _ => false,
+    }
+}
+
+//Plays the cdg without audio up to its first fully drawn screen. That's the
+//frame with the most drawn before the screen is first cleared, which on a
+//karaoke disc is the title card naming the song, artist and producer.
+fn title_frame(kfile: &Kfile) -> Result<RgbaImage, Error> {
+    let mut scsi = cdg::SubchannelStreamIter::new(kfile.open_cdg()?);
+    let mut cdg_interp = cdg_renderer::CdgInterpreter::new();
+    let mut frame = RgbaImage::new(CDG_WIDTH, CDG_HEIGHT);
+    let mut best: Option<(usize, RgbaImage)> = None;
+
+    'render: for _ in 0..MAX_SECTORS / SAMPLE_SECTORS {
+        for _ in 0..SAMPLE_SECTORS {
+            match scsi.next() {
+                Some(sector) => {
+                    for cmd in sector {
+                        cdg_interp.handle_cmd(cmd);
+                    }
+                }
+                None => break 'render,
+            }
+        }
+
+        frame.copy_from(&cdg_interp, 0, 0);
+        let drawn = drawn_pixels(&frame);
+        match &best {
+            Some((most, _)) if drawn < *most / 2 => break,
+            Some((most, _)) if drawn <= *most => {}
+            _ if drawn > 0 => best = Some((drawn, frame.clone())),
+            _ => {}
+        }
+    }
+
+    match best {
+        Some((_, frame)) => Ok(frame),
+        None => bail!("Nothing is drawn in the cdg"),
+    }
+}
+
+//Pixels not in the background color, taken from the corner which is always
+//border
+fn drawn_pixels(frame: &RgbaImage) -> usize {
+    let background = frame.get_pixel(0, 0);
+    frame.pixels().filter(|pixel| *pixel != background).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karaoke::test_util::TestDir;
+
+    //Cdg packet on the graphics channel
+    fn packet(instruction: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; 24];
+        packet[0] = 9;
+        packet[1] = instruction;
+        packet[4..4 + data.len()].copy_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn test_preview() {
+        let dir = TestDir::new("preview");
+        let data_path = dir.path();
+
+        //A title card of solid tiles, then cleared for the lyrics, which only
+        //draw a single tile
+        let clear = packet(1, &[0]);
+        let tile = |row, col| {
+            packet(
+                6,
+                &[
+                    0, 1, row, col, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f,
+                    0x3f, 0x3f,
+                ],
+            )
+        };
+        let mut cdg = clear.clone();
+        for row in 1..17 {
+            for col in 1..49 {
+                cdg.extend(tile(row, col));
+            }
+        }
+        //Held on screen for a couple seconds
+        cdg.resize(cdg.len() + 96 * 150, 0);
+        cdg.extend(clear);
+        cdg.extend(tile(8, 24));
+        cdg.resize(96 * 75 * 4, 0);
+        fs::write(data_path.join("title.cdg"), &cdg).unwrap();
+
+        let kfile = Kfile {
+            id: 1,
+            cdg_path: data_path.join("title.cdg"),
+            audio_path: data_path.join("title.cdg"),
+            ..Kfile::default()
+        };
+        let cache_dir = data_path.join(PREVIEW_DIR);
+        let png = preview(&kfile, &cache_dir).unwrap();
+        assert_eq!(fs::read(cache_dir.join("1.png")).unwrap(), png);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        let frame = image::load_from_memory(&png).unwrap().to_rgba();
+        assert_eq!(frame.dimensions(), (CDG_WIDTH, CDG_HEIGHT));
+        assert_eq!(drawn_pixels(&frame), 16 * 48 * 6 * 12);
+
+        //An empty cdg has no preview
+        fs::write(data_path.join("title.cdg"), []).unwrap();
+        assert!(title_frame(&kfile).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use failure::{format_err, Error};
+use karaoke::{
+    collection::{load_collection, Database, OverridesDB, AUDIO_EXTENSIONS, COLLECTION},
+    log_error,
+    store::CollectionDB,
+    CONFIG,
+};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::{path::Path, sync::mpsc::channel, thread, time::Duration};
+
+pub fn run() {
+    thread::spawn(move || {
+        if let Err(e) = watch() {
+            log_error(&e);
+        }
+    });
+}
+
+//Watch the song directories and refresh the collection whenever a song file is
+//added, removed or renamed. Events are debounced, then the incremental refresh
+//works out what changed and the new collection is swapped in for the site.
+fn watch() -> Result<(), Error> {
+    let (send, recv) = channel();
+    let mut watcher = notify::watcher(send, Duration::from_secs(2))?;
+    let libraries = CONFIG.libraries();
+    for library in libraries.iter() {
+        watcher.watch(&library.path, RecursiveMode::Recursive)?;
+        log::info!("Watching song dir for changes: {:?}", library.path);
+    }
+
+    let collection_db = CollectionDB::initialize(&CONFIG.data_path)?;
+    let overrides_db = OverridesDB::initialize(&CONFIG.data_path)?;
+
+    loop {
+        let event = recv
+            .recv()
+            .map_err(|e| format_err!("Watcher stopped: {}", e))?;
+        if !is_song_event(&event) {
+            continue;
+        }
+
+        //Pick up the rest of a burst (ie. a folder being copied in) before refreshing
+        while recv.recv_timeout(Duration::from_secs(1)).is_ok() {}
+
+        //A failed refresh shouldn't stop the watcher, the next change will retry it
+        let collection = collection_db.refresh(&libraries).and_then(|summary| {
+            if summary.added + summary.updated + summary.removed == 0 {
+                Ok(None)
+            } else {
+                load_collection(&collection_db, &overrides_db).map(Some)
+            }
+        });
+        match collection {
+            Ok(Some(collection)) => {
+                *COLLECTION.write().unwrap() = collection;
+                log::info!("Collection updated from song dir changes");
+            }
+            Ok(None) => {}
+            Err(e) => log_error(&e),
+        }
+    }
+}
+
+fn is_song_event(event: &DebouncedEvent) -> bool {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path) => is_song_file(path),
+        DebouncedEvent::Rename(from, to) => is_song_file(from) || is_song_file(to),
+        DebouncedEvent::Rescan => true,
+        _ => false,
+    }
+}
+
+//Directories are included, since removing or renaming one takes its songs with it
+fn is_song_file(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => {
+            let ext = ext.to_lowercase();
+            ext == "cdg" || ext == "zip" || AUDIO_EXTENSIONS.contains(&ext.as_str())
+        }
+        None => true,
+    }
+}
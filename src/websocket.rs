@@ -12,14 +12,52 @@ use websocket::{sync::Server, OwnedMessage};
 #[derive(Serialize, Deserialize)]
 pub struct WsMessage {
     pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tempo: Option<u8>,
 }
 
 impl WsMessage {
     fn json(command: &str) -> String {
-        serde_json::to_string(&WsMessage {
+        WsMessage::new(command).to_json()
+    }
+
+    fn new(command: &str) -> WsMessage {
+        WsMessage {
             command: command.to_string(),
-        })
-        .unwrap()
+            seconds: None,
+            tempo: None,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    //Commands passed on to the web player, which can't change key. It changes
+    //tempo by playing faster, which changes pitch too.
+    fn live(cmd: &LiveCommand) -> Option<String> {
+        match cmd {
+            LiveCommand::Stop => Some(WsMessage::json("stop")),
+            LiveCommand::Pause => Some(WsMessage::json("pause")),
+            LiveCommand::Resume => Some(WsMessage::json("resume")),
+            LiveCommand::Seek { secs } => Some(
+                WsMessage {
+                    seconds: Some(*secs),
+                    ..WsMessage::new("seek")
+                }
+                .to_json(),
+            ),
+            LiveCommand::Tempo { tempo } => Some(
+                WsMessage {
+                    tempo: Some(*tempo),
+                    ..WsMessage::new("tempo")
+                }
+                .to_json(),
+            ),
+            LiveCommand::Key { .. } => None,
+        }
     }
 }
 
@@ -64,15 +102,13 @@ pub fn start_ws_server(receiver: BroadcastReceiver<LiveCommand>) -> Result<(), E
                     let mut now = time::Instant::now();
                     loop {
                         if let Ok(cmd) = live_receiver.try_recv() {
-                            match cmd {
-                                LiveCommand::Stop => {
-                                    let message = OwnedMessage::Text(WsMessage::json("stop"));
-                                    if let Err(e) = sender.send_message(&message) {
-                                        log_error(&format_err!("Websocket error: {}", e));
-                                        break;
-                                    }
-                                    log::debug!("Stop command sent to {}", ip);
+                            if let Some(text) = WsMessage::live(&cmd) {
+                                let message = OwnedMessage::Text(text);
+                                if let Err(e) = sender.send_message(&message) {
+                                    log_error(&format_err!("Websocket error: {}", e));
+                                    break;
                                 }
+                                log::debug!("{:?} command sent to {}", cmd, ip);
                             }
                         }
                         select! {
@@ -1,12 +1,10 @@
 use crossbeam_channel::{select, Receiver, Sender};
 use failure::format_err;
 use karaoke::{
-    channel::{
-        LiveCommand, PlayerCommand, WorkerCommand, LIVE_CHANNEL, PLAYER_CHANNEL, WORKER_CHANNEL,
-    },
-    collection::Kfile,
+    channel::{LiveCommand, WorkerCommand, LIVE_CHANNEL, WORKER_CHANNEL},
     log_error,
-    queue::PLAY_QUEUE,
+    playback::{Playback, PlayerAction, PLAYBACK},
+    queue::QueueEntry,
     CONFIG,
 };
 use multiqueue::BroadcastSender;
@@ -17,195 +15,153 @@ use std::{
 
 pub fn run() {
     thread::spawn(move || {
-        if CONFIG.use_web_player {
-            web_worker();
+        let output: Box<dyn Output> = if CONFIG.use_web_player {
+            Box::new(WebOutput::new())
         } else {
-            native_worker();
+            Box::new(NativeOutput::new())
+        };
+        let mut worker = Worker::new(output);
+
+        loop {
+            select! {
+                recv(worker.worker_receiver) -> cmd => {
+                    match cmd {
+                        Ok(cmd) => worker.process_cmd(cmd),
+                        Err(e) => log_error(&format_err!("{:?}", e))
+                    }
+                },
+                default() => {},
+            }
+            thread::sleep(time::Duration::from_millis(50));
         }
     });
 }
 
-fn native_worker() {
-    let worker = NativeWorker::new();
-    loop {
-        select! {
-            recv(worker.worker_receiver) -> cmd => {
-                match cmd {
-                    Ok(cmd) => worker.process_cmd(cmd),
-                    Err(e) => log_error(&format_err!("{:?}", e))
-                }
-            },
-            default() => {},
-        }
-        thread::sleep(time::Duration::from_millis(50));
-    }
-}
-
-fn web_worker() {
-    let (send, recv) = multiqueue::broadcast_queue::<LiveCommand>(5);
-    let mut worker = WebWorker::new(send);
-
-    // Start websocket server, will receive commands on Player Receiver
-    thread::spawn(move || loop {
-        if let Err(e) = karaoke::websocket::start_ws_server(recv.clone()) {
-            log_error(&e);
-        };
-    });
-
-    loop {
-        select! {
-            recv(worker.worker_receiver) -> cmd => {
-                match cmd {
-                    Ok(cmd) => worker.process_cmd(cmd),
-                    Err(e) => log_error(&format_err!("{:?}", e))
-                }
-            },
-            default() => {},
-        }
-        thread::sleep(time::Duration::from_millis(50));
-    }
+//Where songs are played, the native player's window or browsers on the player
+//page. Both take songs from the playback themselves, this only passes on what
+//commands need them to do. It's sent with the playback still locked, so the
+//player can't take another song before it arrives.
+trait Output {
+    fn send(&mut self, action: PlayerAction, playback: &mut Playback);
 }
 
-#[derive(Debug)]
-struct NativeWorker {
-    worker_receiver: Receiver<WorkerCommand>,
-    player_sender: Sender<PlayerCommand>,
+struct NativeOutput {
     live_sender: Sender<LiveCommand>,
-    queue: Arc<Mutex<Vec<Kfile>>>,
 }
 
-impl NativeWorker {
+impl NativeOutput {
     fn new() -> Self {
-        let worker_receiver = WORKER_CHANNEL.1.clone();
-        let player_sender = PLAYER_CHANNEL.0.clone();
-        let live_sender = LIVE_CHANNEL.0.clone();
-        let queue = PLAY_QUEUE.clone();
-        NativeWorker {
-            worker_receiver,
-            player_sender,
-            live_sender,
-            queue,
+        NativeOutput {
+            live_sender: LIVE_CHANNEL.0.clone(),
         }
     }
+}
 
-    fn process_cmd(&self, cmd: WorkerCommand) {
-        match cmd {
-            WorkerCommand::Stop => self.stop(),
-            WorkerCommand::Next => self.next(),
-            WorkerCommand::PlayNow { kfile } => self.play_now(kfile),
-            WorkerCommand::ClearQueue => self.clear_queue(),
-            WorkerCommand::AddQueue { kfile } => self.add_queue(kfile),
+impl Output for NativeOutput {
+    fn send(&mut self, action: PlayerAction, _: &mut Playback) {
+        if let Err(e) = self.live_sender.send(live_command(action)) {
+            log_error(&format_err!("{}", e));
         }
     }
+}
 
-    fn stop(&self) {
-        self.clear_queue();
+struct WebOutput {
+    live_sender: BroadcastSender<LiveCommand>,
+}
 
-        if self.live_sender.is_empty() {
-            self.live_sender.send(LiveCommand::Stop).unwrap();
-        }
-    }
+impl WebOutput {
+    fn new() -> Self {
+        let (send, recv) = multiqueue::broadcast_queue::<LiveCommand>(5);
 
-    fn next(&self) {
-        let queue = self.queue.lock().unwrap();
-        if queue.is_empty() {
-            drop(queue);
-            return;
-        }
-        drop(queue);
-        self.live_sender.send(LiveCommand::Stop).unwrap();
-    }
+        // Start websocket server, will receive commands on Player Receiver
+        thread::spawn(move || loop {
+            if let Err(e) = karaoke::websocket::start_ws_server(recv.clone()) {
+                log_error(&e);
+            };
+        });
 
-    fn play_now(&self, kfile: Kfile) {
-        self.live_sender.send(LiveCommand::Stop).unwrap();
-        self.player_sender
-            .send(PlayerCommand::Play { kfile })
-            .unwrap();
+        WebOutput { live_sender: send }
     }
+}
 
-    fn clear_queue(&self) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.clear();
-        drop(queue);
+impl Output for WebOutput {
+    fn send(&mut self, action: PlayerAction, playback: &mut Playback) {
+        if let Err(e) = self.live_sender.try_send(live_command(action)) {
+            log_error(&format_err!("{}", e));
+        };
+        //Browsers don't say when they've stopped, and there may not be one
+        //open to stop. The song got as far as the last position reported.
+        if action == PlayerAction::Stop {
+            if let Some(id) = playback.playing().map(|entry| entry.id) {
+                let played = playback.elapsed();
+                playback.stopped(id, Some(played));
+            }
+        }
     }
+}
 
-    fn add_queue(&self, kfile: Kfile) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push(kfile);
-        drop(queue);
+fn live_command(action: PlayerAction) -> LiveCommand {
+    match action {
+        PlayerAction::Stop => LiveCommand::Stop,
+        PlayerAction::Pause => LiveCommand::Pause,
+        PlayerAction::Resume => LiveCommand::Resume,
+        PlayerAction::Seek(secs) => LiveCommand::Seek { secs },
+        PlayerAction::Key(key) => LiveCommand::Key { key },
+        PlayerAction::Tempo(tempo) => LiveCommand::Tempo { tempo },
     }
 }
 
-struct WebWorker {
+struct Worker {
     worker_receiver: Receiver<WorkerCommand>,
-    live_sender: BroadcastSender<LiveCommand>,
-    queue: Arc<Mutex<Vec<Kfile>>>,
+    playback: Arc<Mutex<Playback>>,
+    output: Box<dyn Output>,
 }
 
-impl WebWorker {
-    fn new(live_sender: BroadcastSender<LiveCommand>) -> Self {
-        let worker_receiver = WORKER_CHANNEL.1.clone();
-        let queue = PLAY_QUEUE.clone();
-
-        WebWorker {
-            worker_receiver,
-            live_sender,
-            queue,
+impl Worker {
+    fn new(output: Box<dyn Output>) -> Self {
+        Worker {
+            worker_receiver: WORKER_CHANNEL.1.clone(),
+            playback: PLAYBACK.clone(),
+            output,
         }
     }
 
     fn process_cmd(&mut self, cmd: WorkerCommand) {
-        match cmd {
-            WorkerCommand::Stop => self.stop(),
-            WorkerCommand::Next => self.next(),
-            WorkerCommand::PlayNow { kfile } => self.play_now(kfile),
-            WorkerCommand::ClearQueue => self.clear_queue(),
-            WorkerCommand::AddQueue { kfile } => self.add_queue(kfile),
-        }
-    }
-
-    fn stop(&mut self) {
-        self.clear_queue();
-
-        if let Err(e) = self.live_sender.try_send(LiveCommand::Stop) {
-            log_error(&format_err!("{}", e));
+        let mut playback = self.playback.lock().unwrap();
+        let action = match cmd {
+            WorkerCommand::Stop => playback.stop(),
+            WorkerCommand::Next => playback.next(),
+            WorkerCommand::PlayNow { kfile } => playback.play_now(QueueEntry::new(kfile, None)),
+            WorkerCommand::Pause => playback.pause(),
+            WorkerCommand::Resume => playback.resume(),
+            WorkerCommand::Seek { secs } => playback.seek(secs),
+            WorkerCommand::Restart => playback.seek(0),
+            WorkerCommand::SetKey { id, key } => playback.set_key(id, key),
+            WorkerCommand::SetTempo { id, tempo } => playback.set_tempo(id, tempo),
+            WorkerCommand::ClearQueue => {
+                playback.clear();
+                None
+            }
+            WorkerCommand::AddQueue { entry } => {
+                playback.add(entry);
+                None
+            }
+            WorkerCommand::RemoveQueue { id } => {
+                playback.remove(id);
+                None
+            }
+            WorkerCommand::MoveQueue { id, position } => {
+                playback.move_to(id, position);
+                None
+            }
+            WorkerCommand::SwapQueue { id, other } => {
+                playback.swap(id, other);
+                None
+            }
         };
-    }
-
-    fn next(&mut self) {
-        let mut queue = self.queue.lock().unwrap();
-        if queue.is_empty() {
-            drop(queue);
-            return;
+        if let Some(action) = action {
+            self.output.send(action, &mut playback);
         }
-        queue.remove(0);
-        drop(queue);
-        if let Err(e) = self.live_sender.try_send(LiveCommand::Stop) {
-            log_error(&format_err!("{}", e));
-        };
-    }
-
-    fn play_now(&mut self, kfile: Kfile) {
-        let mut queue = self.queue.lock().unwrap();
-        if !queue.is_empty() {
-            queue.remove(0);
-        }
-        queue.insert(0, kfile);
-        drop(queue);
-        if let Err(e) = self.live_sender.try_send(LiveCommand::Stop) {
-            log_error(&format_err!("{}", e));
-        };
-    }
-
-    fn clear_queue(&self) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.clear();
-        drop(queue);
-    }
-
-    fn add_queue(&self, kfile: Kfile) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push(kfile);
-        drop(queue);
+        log::debug!("Player is {:?}", playback.state());
     }
 }